@@ -0,0 +1,169 @@
+//! Throughput of delivering locally emitted messages to subscribers, with the
+//! dispatcher task against the previous design of one spawned task per event.
+//!
+//! Run with `cargo bench --bench dispatch > /dev/null`, results go to stderr
+//! since subscriber streams print to stdout.
+
+use futures::StreamExt;
+use grpc_logger::server_build::logging::v2::field_value::Value;
+use grpc_logger::server_build::logging::v2::log_service_server::LogService;
+use grpc_logger::server_build::logging::v2::{Level, LogMessage, SubscribeRequest};
+use grpc_logger::LoggingService;
+use std::time::{Duration, Instant};
+use tonic::Request;
+
+const MESSAGES: u64 = 100_000;
+const SUBSCRIBERS: usize = 4;
+
+#[derive(Debug, Clone, Copy)]
+enum Design {
+    /// `tokio::spawn(service.broadcast_log(log))` for every message
+    SpawnPerEvent,
+    /// `service.dispatch(log)`, delivered by the dispatcher task
+    Dispatcher,
+}
+
+/// What a subscriber saw
+#[derive(Debug, Default)]
+struct Received {
+    messages: u64,
+    // Reported by the queue when the subscriber fell behind
+    dropped: u64,
+    out_of_order: u64,
+}
+
+fn message(n: u64) -> LogMessage {
+    LogMessage {
+        level: Level::Info as i32,
+        message: format!("message {}", n),
+        target: Some("bench".to_string()),
+        server_id: Some("bench".to_string()),
+        fields: [("n".to_string(), Value::U64Value(n).into())].into(),
+        ..Default::default()
+    }
+}
+
+fn u64_field(log: &LogMessage, name: &str) -> Option<u64> {
+    match log.fields.get(name)?.value {
+        Some(Value::U64Value(n)) => Some(n),
+        _ => None,
+    }
+}
+
+async fn subscribe(
+    service: &LoggingService,
+    client_id: String,
+) -> tokio::task::JoinHandle<Received> {
+    let request = Request::new(SubscribeRequest {
+        client_id,
+        ..Default::default()
+    });
+    let mut stream = service
+        .subscribe_to_logs(request)
+        .await
+        .unwrap()
+        .into_inner();
+
+    tokio::spawn(async move {
+        let mut received = Received::default();
+        let mut last = None;
+        while let Some(Ok(log)) = stream.next().await {
+            if let Some(dropped) = u64_field(&log, "dropped") {
+                received.dropped += dropped;
+            }
+            let Some(n) = u64_field(&log, "n") else {
+                continue;
+            };
+            received.messages += 1;
+            if last.is_some_and(|last| n < last) {
+                received.out_of_order += 1;
+            }
+            last = Some(n);
+        }
+        received
+    })
+}
+
+async fn run(design: Design) {
+    let service = LoggingService::new();
+    let mut subscribers = Vec::with_capacity(SUBSCRIBERS);
+    for i in 0..SUBSCRIBERS {
+        subscribers.push(subscribe(&service, format!("bench-{}", i)).await);
+    }
+
+    // Emitting is what the tracing layer pays for on every event, delivering
+    // ends once every message is in the subscriber queues
+    let start = Instant::now();
+    let emitted = match design {
+        Design::SpawnPerEvent => {
+            let mut tasks = Vec::with_capacity(MESSAGES as usize);
+            for n in 0..MESSAGES {
+                let service = service.clone();
+                tasks.push(tokio::spawn(async move {
+                    service.broadcast_log(message(n)).await
+                }));
+            }
+            let emitted = start.elapsed();
+            for task in tasks {
+                task.await.unwrap();
+            }
+            emitted
+        }
+        Design::Dispatcher => {
+            for n in 0..MESSAGES {
+                service.dispatch(message(n));
+            }
+            let emitted = start.elapsed();
+            service.flush().await;
+            emitted
+        }
+    };
+    let delivered = start.elapsed();
+
+    // Let the streams catch up before closing them
+    while service
+        .list_clients()
+        .await
+        .iter()
+        .any(|client| client.queue_depth > 0)
+    {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    for i in 0..SUBSCRIBERS {
+        service.disconnect_client(&format!("bench-{}", i)).await;
+    }
+
+    let mut received = Received::default();
+    for subscriber in subscribers {
+        let subscriber = subscriber.await.unwrap();
+        received.messages += subscriber.messages;
+        received.dropped += subscriber.dropped;
+        received.out_of_order += subscriber.out_of_order;
+    }
+
+    eprintln!(
+        "{:<14} emitted in {:>9.1?}  delivered in {:>9.1?} ({:>7.0} msg/s)  \
+         received {:>6} + dropped {:>6} of {}  out of order {}",
+        format!("{:?}", design),
+        emitted,
+        delivered,
+        MESSAGES as f64 / delivered.as_secs_f64(),
+        received.messages,
+        received.dropped,
+        MESSAGES * SUBSCRIBERS as u64,
+        received.out_of_order,
+    );
+}
+
+fn main() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    eprintln!(
+        "{} messages to {} subscribers, 3 rounds per design",
+        MESSAGES, SUBSCRIBERS
+    );
+    for _ in 0..3 {
+        for design in [Design::SpawnPerEvent, Design::Dispatcher] {
+            runtime.block_on(run(design));
+        }
+    }
+}
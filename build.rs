@@ -12,6 +12,6 @@ fn main() {
     tonic_build::configure()
         .protoc_arg("--experimental_allow_proto3_optional")
         .file_descriptor_set_path(descriptor_path)
-        .compile_protos(&["log_service.proto"], &["proto"])
+        .compile_protos(&["log_service.proto", "log_service_v2.proto"], &["proto"])
         .unwrap_or_else(|e| panic!("Failed to compile proto files: {}", e));
 }
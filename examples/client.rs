@@ -1,43 +1,43 @@
-use grpc_logger::config::ClientRetryConfig;
+use grpc_logger::convert::to_origin_time;
 use grpc_logger::{config::load_config, LogConfig};
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 use tokio::time::sleep;
 use tonic::Request;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 use tracing_subscriber::{fmt, EnvFilter};
 pub mod logging {
-    tonic::include_proto!("logging");
+    tonic::include_proto!("logging.v2");
 }
 use clap::Parser;
 use logging::log_service_client::LogServiceClient;
-use logging::SubscribeRequest;
+use logging::{ClientType, LogFilter, ResumeCursor, SubscribeRequest};
+use std::collections::HashMap;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     #[arg(long, default_value = "default-client")]
     client_id: String,
+    /// Bearer token, when the server has authentication enabled
+    #[arg(long)]
+    token: Option<String>,
 }
 
 async fn connect_with_retry(
     config: &LogConfig,
-) -> Result<LogServiceClient<tonic::transport::Channel>, tonic::transport::Error> {
+) -> Result<LogServiceClient<tonic::transport::Channel>, Box<dyn std::error::Error + Send + Sync>> {
     let retry_config = &config.client_retry;
     let base_delay = Duration::from_secs(retry_config.base_delay_secs);
-    let server_addr = format!(
-        "http://{}:{}",
-        config.grpc.as_ref().unwrap().address,
-        config.grpc.as_ref().unwrap().port
-    );
+    // Plaintext or TLS, as configured in the grpc section
+    let endpoint = config.grpc.as_ref().ok_or("Missing grpc section")?.endpoint()?;
+    let server_addr = endpoint.uri().to_string();
 
     let mut retry_count = 0;
     loop {
-        let config = load_config("examples/client.yaml")?;
-        info!("Starting log client with ID: {}", args.client_id);
-        match LogServiceClient::connect(server_addr.clone()).await {
-            Ok(client) => {
+        match endpoint.connect().await {
+            Ok(channel) => {
                 info!("Successfully connected to log server at {}", server_addr);
-                return Ok(client);
+                return Ok(LogServiceClient::new(channel));
             }
             Err(e) => {
                 retry_count += 1;
@@ -46,7 +46,7 @@ async fn connect_with_retry(
                         "Failed to connect after {} retries. Exiting.",
                         retry_config.max_retries
                     );
-                    return Err(e);
+                    return Err(e.into());
                 }
                 let delay = base_delay.mul_f32(1.5f32.powi(retry_count as i32));
                 error!(
@@ -77,50 +77,105 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
 
     // Load configuration
     let config = load_config("examples/client.yaml")?;
-    info!("Starting log client...");
+    info!("Starting log client with ID: {}", args.client_id);
+
+    // Last sequence seen per origin, used to fill the gap after a reconnect,
+    // and the server epoch they belong to
+    let mut last_sequences: HashMap<String, u64> = HashMap::new();
+    let mut epoch = String::new();
+    // When the first subscription started, to replay what was missed when
+    // the stream drops before any message arrived
+    let mut subscribed_at: Option<SystemTime> = None;
 
     loop {
         let mut client = connect_with_retry(&config).await?;
         let request = SubscribeRequest {
-            client_id: "rust-client-1".to_string(),
+            client_id: args.client_id.clone(),
+            client_type: ClientType::Server as i32,
+            server_name: args.client_id.clone(),
+            // Let the server drop internal transport logs before sending them
+            filter: Some(LogFilter {
+                exclude_targets: vec!["h2::".to_string(), "tonic::".to_string()],
+                ..Default::default()
+            }),
+            resume: (!last_sequences.is_empty()).then(|| ResumeCursor {
+                last_sequences: last_sequences.clone(),
+                epoch: epoch.clone(),
+            }),
+            history_since: subscribed_at
+                .filter(|_| last_sequences.is_empty())
+                .map(Into::into),
+            ..Default::default()
         };
         debug!("Subscribing to log stream...");
 
-        match client
-            .subscribe_to_logs(Request::new(request.clone()))
-            .await
-        {
+        let mut request = Request::new(request);
+        if let Some(token) = &args.token {
+            request
+                .metadata_mut()
+                .insert("authorization", format!("Bearer {}", token).parse()?);
+        }
+
+        match client.subscribe_to_logs(request).await {
             Ok(response) => {
                 info!("Connected to log server. Waiting for logs...");
+                subscribed_at.get_or_insert_with(SystemTime::now);
                 let mut stream = response.into_inner();
-                while let Some(log) = stream.message().await? {
-                    // Check if target exists and filter internal logs
-                    let should_display = log.target.as_ref().map_or(true, |target| {
-                        !target.starts_with("h2::") && !target.starts_with("tonic::")
-                    });
-
-                    if should_display {
-                        // Format log message with only available fields
-                        let formatted_log = format!(
-                            "Log {{ timestamp: {}, level: {}, message: '{}'{}{}{}{} }}",
-                            log.timestamp.unwrap_or_default(),
-                            log.level.unwrap_or_default(),
-                            log.message,
-                            log.target
-                                .as_ref()
-                                .map_or(String::new(), |t| format!(", target: '{}'", t)),
-                            log.thread_id
-                                .as_ref()
-                                .map_or(String::new(), |t| format!(", thread: '{}'", t)),
-                            log.file
-                                .as_ref()
-                                .map_or(String::new(), |f| format!(", file: '{}'", f)),
-                            log.line
-                                .as_ref()
-                                .map_or(String::new(), |l| format!(", line: {}", l)),
-                        );
-                        info!("{}", formatted_log);
+                loop {
+                    let log = match stream.message().await {
+                        Ok(Some(log)) => log,
+                        Ok(None) => break,
+                        Err(e) => {
+                            error!("Stream error: {}", e);
+                            break;
+                        }
+                    };
+                    if let Some(gap) = &log.gap {
+                        if gap.restarted {
+                            warn!(
+                                "Server restarted, messages after {} from '{}' may be lost",
+                                gap.first_missing - 1,
+                                gap.server_id
+                            );
+                        } else {
+                            warn!(
+                                "Missed messages {}..={} from '{}'",
+                                gap.first_missing, gap.last_missing, gap.server_id
+                            );
+                        }
+                    } else if log.sequence > 0 {
+                        // Sequences of a previous epoch mean nothing to the new one
+                        if log.epoch != epoch {
+                            last_sequences.clear();
+                            epoch = log.epoch.clone();
+                        }
+                        last_sequences
+                            .insert(log.server_id.clone().unwrap_or_default(), log.sequence);
                     }
+
+                    // Format log message with only available fields
+                    let formatted_log = format!(
+                        "Log {{ timestamp: {}, level: {}, message: '{}'{}{}{}{} }}",
+                        log.timestamp
+                            .as_ref()
+                            .and_then(|t| to_origin_time(t, log.utc_offset_secs))
+                            .map_or(String::new(), |t| t.to_rfc3339()),
+                        log.level().as_str_name(),
+                        log.message,
+                        log.target
+                            .as_ref()
+                            .map_or(String::new(), |t| format!(", target: '{}'", t)),
+                        log.thread_id
+                            .as_ref()
+                            .map_or(String::new(), |t| format!(", thread: '{}'", t)),
+                        log.file
+                            .as_ref()
+                            .map_or(String::new(), |f| format!(", file: '{}'", f)),
+                        log.line
+                            .as_ref()
+                            .map_or(String::new(), |l| format!(", line: {}", l)),
+                    );
+                    info!("{}", formatted_log);
                 }
                 debug!("Stream ended. Attempting to reconnect...");
             }
@@ -1,6 +1,10 @@
+use grpc_logger::grpc::GrpcConfig;
 use grpc_logger::{config::load_config, LogConfig, LoggingService};
 use std::time::Duration;
 use tokio::time::sleep;
+use tonic_health::pb::health_check_response::ServingStatus;
+use tonic_health::pb::health_client::HealthClient;
+use tonic_health::pb::HealthCheckRequest;
 use tracing::{debug, error, info};
 
 async fn init_with_retry(
@@ -13,7 +17,7 @@ async fn init_with_retry(
 
     loop {
         debug!("Attempting to initialize logging service");
-        match service.init(&config).await {
+        match service.init(config).await {
             Ok(_) => {
                 info!("Successfully initialized log server");
                 return Ok(());
@@ -22,7 +26,7 @@ async fn init_with_retry(
                 retry_count += 1;
                 if retry_count > retry_config.max_retries {
                     error!("Failed to initialize after {} retries", retry_count);
-                    return Err(e.into());
+                    return Err(e);
                 }
                 let delay = base_delay.mul_f32(1.5f32.powi(retry_count as i32));
                 error!(
@@ -36,6 +40,23 @@ async fn init_with_retry(
     }
 }
 
+/// Ask the server's `grpc.health.v1.Health` service whether it is serving
+async fn check_health(grpc: &GrpcConfig) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let channel = grpc.endpoint()?.connect().await?;
+    let mut client = HealthClient::new(channel);
+    let response = client
+        .check(HealthCheckRequest {
+            service: String::new(),
+        })
+        .await?
+        .into_inner();
+
+    match response.status() {
+        ServingStatus::Serving => Ok(()),
+        status => Err(format!("server reported {}", status.as_str_name()).into()),
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Load configuration with retry settings
@@ -47,11 +68,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Initialize logging with retry mechanism
     init_with_retry(&config, service.clone()).await?;
 
+    let grpc = config.grpc.as_ref().ok_or("Missing grpc section")?;
+
     // Main loop with reconnection logic
     loop {
         info!("Retry example - heartbeat message");
 
-        if let Err(e) = service.check_connection().await {
+        if let Err(e) = check_health(grpc).await {
             error!("Connection lost: {}. Reconnecting...", e);
             init_with_retry(&config, service.clone()).await?;
         }
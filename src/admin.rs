@@ -0,0 +1,113 @@
+use crate::convert::to_timestamp;
+use crate::server_build::logging::v2::admin_service_server::AdminService;
+use crate::server_build::logging::v2::{
+    ClientInfo, DisconnectClientRequest, DisconnectClientResponse, GetFilterRequest,
+    GetFilterResponse, ListClientsRequest, ListClientsResponse, SetFilterRequest,
+    SetFilterResponse,
+};
+use crate::server_build::{shared_filter, LoggingService};
+use tonic::{Request, Response, Status};
+use tracing_subscriber::EnvFilter;
+
+#[tonic::async_trait]
+impl AdminService for LoggingService {
+    async fn list_clients(
+        &self,
+        _request: Request<ListClientsRequest>,
+    ) -> Result<Response<ListClientsResponse>, Status> {
+        let clients = LoggingService::list_clients(self)
+            .await
+            .into_iter()
+            .map(|info| ClientInfo {
+                client_id: info.client_id,
+                client_type: info.client_type as i32,
+                server_name: info.server_name,
+                peer_address: info
+                    .peer_address
+                    .map(|addr| addr.to_string())
+                    .unwrap_or_default(),
+                connected_at: Some(to_timestamp(info.connected_at)),
+                identity: info.identity.unwrap_or_default(),
+                messages_sent: info.messages_sent,
+                queue_depth: info.queue_depth,
+                paused: info.paused,
+                acked_sequences: info.acked_sequences,
+            })
+            .collect();
+
+        Ok(Response::new(ListClientsResponse { clients }))
+    }
+
+    async fn disconnect_client(
+        &self,
+        request: Request<DisconnectClientRequest>,
+    ) -> Result<Response<DisconnectClientResponse>, Status> {
+        let client_id = request.into_inner().client_id;
+        if LoggingService::disconnect_client(self, &client_id).await {
+            Ok(Response::new(DisconnectClientResponse {}))
+        } else {
+            Err(Status::not_found(format!("No client with id {}", client_id)))
+        }
+    }
+
+    async fn get_filter(
+        &self,
+        _request: Request<GetFilterRequest>,
+    ) -> Result<Response<GetFilterResponse>, Status> {
+        let output_filters = LoggingService::output_filters(self).await;
+        match LoggingService::get_filter(self).await {
+            Some(filter) => Ok(Response::new(GetFilterResponse {
+                filter,
+                output_filters,
+            })),
+            None => Err(Status::failed_precondition(
+                "Logging was not set up by this service",
+            )),
+        }
+    }
+
+    async fn set_filter(
+        &self,
+        request: Request<SetFilterRequest>,
+    ) -> Result<Response<SetFilterResponse>, Status> {
+        let request = request.into_inner();
+        let outputs = LoggingService::output_filters(self).await.len();
+        if outputs == 0 {
+            return Err(Status::failed_precondition(
+                "Logging was not set up by this service",
+            ));
+        }
+        // The same filter for every output, or one per output
+        let directives = if request.output_filters.is_empty() {
+            vec![request.filter; outputs]
+        } else if !request.filter.is_empty() {
+            return Err(Status::invalid_argument(
+                "Set either filter or output_filters",
+            ));
+        } else if request.output_filters.len() != outputs {
+            return Err(Status::invalid_argument(format!(
+                "Expected {} output filters, got {}",
+                outputs,
+                request.output_filters.len()
+            )));
+        } else {
+            request.output_filters
+        };
+        let filters = directives
+            .iter()
+            .map(|directives| EnvFilter::builder().parse(directives))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Status::invalid_argument(format!("Invalid filter: {}", e)))?;
+        let output_filters: Vec<String> = filters.iter().map(ToString::to_string).collect();
+
+        let previous_output_filters = LoggingService::set_output_filters(self, filters)
+            .await
+            .map_err(|e| Status::failed_precondition(e.to_string()))?;
+        Ok(Response::new(SetFilterResponse {
+            previous_filter: shared_filter(&previous_output_filters).unwrap_or_default(),
+            filter: shared_filter(&output_filters).unwrap_or_default(),
+            previous_output_filters,
+            output_filters,
+        }))
+    }
+}
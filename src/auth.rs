@@ -0,0 +1,215 @@
+//! Bearer token authentication for the log and admin services.
+
+use crate::grpc::{AuthConfig, AuthToken};
+use std::sync::Arc;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+/// Identity of the token a request was authenticated with, stored in the
+/// request extensions
+#[derive(Debug, Clone)]
+pub(crate) struct AuthIdentity(pub String);
+
+/// Checks the `authorization: Bearer <token>` metadata against the configured
+/// tokens
+#[derive(Debug, Clone)]
+pub(crate) struct TokenAuth {
+    // (token, identity)
+    tokens: Arc<Vec<(String, String)>>,
+    // Let every request through, authentication is off
+    open: bool,
+}
+
+impl TokenAuth {
+    /// Authentication of the log service: its tokens and the admin ones.
+    /// Every request passes when it has no token of its own.
+    pub(crate) fn new(config: Option<&AuthConfig>) -> Self {
+        let tokens: Vec<_> = config
+            .map(|config| config.tokens.iter().chain(&config.admin_tokens).collect())
+            .unwrap_or_default();
+        let open = config.is_none_or(|config| config.tokens.is_empty());
+        Self::with_tokens(tokens, open)
+    }
+
+    /// Authentication of `AdminService`: admin tokens only. Every request is
+    /// refused when there are none.
+    pub(crate) fn admin(config: Option<&AuthConfig>) -> Self {
+        let tokens: Vec<_> = config
+            .map(|config| config.admin_tokens.iter().collect())
+            .unwrap_or_default();
+        Self::with_tokens(tokens, false)
+    }
+
+    fn with_tokens(tokens: Vec<&AuthToken>, open: bool) -> Self {
+        Self {
+            tokens: Arc::new(
+                tokens
+                    .into_iter()
+                    .map(|token| (token.token.clone(), token.identity.clone()))
+                    .collect(),
+            ),
+            open,
+        }
+    }
+
+    /// Whether requests need a valid token
+    pub(crate) fn is_enabled(&self) -> bool {
+        !self.open
+    }
+
+    /// Whether some token can pass, false for an admin service without tokens
+    pub(crate) fn has_tokens(&self) -> bool {
+        !self.tokens.is_empty()
+    }
+
+    fn identify(&self, token: &str) -> Option<&str> {
+        // Compare against every token so timing doesn't reveal which one matched
+        let mut identity = None;
+        for (expected, name) in self.tokens.iter() {
+            if constant_time_eq(expected.as_bytes(), token.as_bytes()) {
+                identity = Some(name.as_str());
+            }
+        }
+        identity
+    }
+}
+
+impl Interceptor for TokenAuth {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if !self.is_enabled() {
+            return Ok(request);
+        }
+        if !self.has_tokens() {
+            return Err(Status::permission_denied(
+                "AdminService is disabled, no grpc.auth.admin_tokens are configured",
+            ));
+        }
+
+        let token = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("Missing bearer token"))?;
+        let identity = self
+            .identify(token.trim())
+            .ok_or_else(|| Status::unauthenticated("Invalid bearer token"))?
+            .to_string();
+
+        request.extensions_mut().insert(AuthIdentity(identity));
+        Ok(request)
+    }
+}
+
+/// Identity the request was authenticated with, if any
+pub(crate) fn identity<T>(request: &Request<T>) -> Option<String> {
+    request
+        .extensions()
+        .get::<AuthIdentity>()
+        .map(|identity| identity.0.clone())
+}
+
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::Code;
+
+    fn token(token: &str, identity: &str) -> AuthToken {
+        AuthToken {
+            token: token.to_string(),
+            identity: identity.to_string(),
+        }
+    }
+
+    fn config() -> AuthConfig {
+        AuthConfig {
+            tokens: vec![token("reader-secret", "reader")],
+            admin_tokens: vec![token("admin-secret", "admin")],
+            client_token: None,
+        }
+    }
+
+    /// Run `auth` on a request with this `authorization` header, returning the
+    /// identity recorded
+    fn call(auth: &TokenAuth, authorization: Option<&str>) -> Result<Option<String>, Code> {
+        let mut request = Request::new(());
+        if let Some(authorization) = authorization {
+            request
+                .metadata_mut()
+                .insert("authorization", authorization.parse().unwrap());
+        }
+        auth.clone()
+            .call(request)
+            .map(|request| identity(&request))
+            .map_err(|status| status.code())
+    }
+
+    #[test]
+    fn records_the_identity_of_the_token() {
+        let auth = TokenAuth::new(Some(&config()));
+        assert_eq!(
+            call(&auth, Some("Bearer reader-secret")),
+            Ok(Some("reader".to_string()))
+        );
+        // Admin tokens are valid for the log service too
+        assert_eq!(
+            call(&auth, Some("Bearer admin-secret")),
+            Ok(Some("admin".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_missing_malformed_and_wrong_tokens() {
+        let auth = TokenAuth::new(Some(&config()));
+        assert_eq!(call(&auth, None), Err(Code::Unauthenticated));
+        assert_eq!(call(&auth, Some("reader-secret")), Err(Code::Unauthenticated));
+        assert_eq!(call(&auth, Some("Basic reader-secret")), Err(Code::Unauthenticated));
+        assert_eq!(call(&auth, Some("Bearer ")), Err(Code::Unauthenticated));
+        assert_eq!(call(&auth, Some("Bearer wrong")), Err(Code::Unauthenticated));
+        assert_eq!(call(&auth, Some("Bearer reader-secre")), Err(Code::Unauthenticated));
+    }
+
+    #[test]
+    fn open_without_tokens() {
+        let auth = TokenAuth::new(None);
+        assert!(!auth.is_enabled());
+        assert_eq!(call(&auth, None), Ok(None));
+
+        let auth = TokenAuth::new(Some(&AuthConfig {
+            tokens: Vec::new(),
+            ..config()
+        }));
+        assert_eq!(call(&auth, Some("Bearer anything")), Ok(None));
+    }
+
+    #[test]
+    fn admin_accepts_admin_tokens_only() {
+        let auth = TokenAuth::admin(Some(&config()));
+        assert_eq!(
+            call(&auth, Some("Bearer admin-secret")),
+            Ok(Some("admin".to_string()))
+        );
+        assert_eq!(call(&auth, Some("Bearer reader-secret")), Err(Code::Unauthenticated));
+        assert_eq!(call(&auth, None), Err(Code::Unauthenticated));
+    }
+
+    #[test]
+    fn admin_refuses_everything_without_admin_tokens() {
+        for config in [
+            None,
+            Some(AuthConfig {
+                admin_tokens: Vec::new(),
+                ..config()
+            }),
+        ] {
+            let auth = TokenAuth::admin(config.as_ref());
+            assert!(auth.is_enabled());
+            assert!(!auth.has_tokens());
+            assert_eq!(call(&auth, Some("Bearer reader-secret")), Err(Code::PermissionDenied));
+        }
+    }
+}
@@ -18,6 +18,7 @@ impl GrpcLoggerClient {
         tokio::spawn(async move {
             let request = tonic::Request::new(SubscribeRequest {
                 client_id: "logger".to_string(),
+                ..Default::default()
             });
 
             println!("GRPC Logger: Starting log subscription");
@@ -1,12 +1,16 @@
-use crate::grpc::GrpcConfig;
-use serde::Deserialize;
+use crate::grpc::{Compression, GrpcConfig};
+use crate::fields::{write_json_str, FieldRecorder, SpanFields};
+use crate::server_build::logging::v2::field_value::Value;
+use crate::server_build::logging::v2::FieldValue;
+use crate::ClientType;
+use serde::{Deserialize, Deserializer};
 use std::fs;
 use tracing_subscriber::fmt;
 use tracing_subscriber::fmt::format::Writer;
 use tracing_subscriber::fmt::time::FormatTime;
 
 // Configuration structs
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum LogOutput {
     Console,
@@ -14,6 +18,103 @@ pub enum LogOutput {
     Grpc,
 }
 
+/// How the console and file outputs write events
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// `[time] LEVEL [server_id] message key=value`
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+    /// `key=value` pairs only
+    Logfmt,
+    /// Shorter text, for terminals
+    Compact,
+}
+
+/// When the file output starts a new file
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    #[default]
+    Never,
+    Minutely,
+    Hourly,
+    Daily,
+    /// Once the file reaches `max_file_size_mb`
+    Size,
+}
+
+/// One destination of the log events. Settings left out fall back to the
+/// top-level ones.
+#[derive(Debug, Deserialize, Clone)]
+pub struct OutputConfig {
+    #[serde(rename = "type")]
+    pub output: LogOutput,
+    pub level: Option<String>,
+    pub format: Option<LogFormat>,
+    pub log_fields: Option<LogFieldsConfig>,
+    /// File outputs only
+    pub file_path: Option<String>,
+    pub file_name: Option<String>,
+}
+
+impl From<LogOutput> for OutputConfig {
+    fn from(output: LogOutput) -> Self {
+        Self {
+            output,
+            level: None,
+            format: None,
+            log_fields: None,
+            file_path: None,
+            file_name: None,
+        }
+    }
+}
+
+/// Outputs of the older single-value form. Events were always broadcast to
+/// gRPC subscribers, and `grpc` printed them to stdout as well.
+fn single_output(output: LogOutput) -> Vec<OutputConfig> {
+    let local = match output {
+        LogOutput::File => LogOutput::File,
+        LogOutput::Console | LogOutput::Grpc => LogOutput::Console,
+    };
+    vec![local.into(), LogOutput::Grpc.into()]
+}
+
+/// `output` is a list of outputs, each either a type or an `OutputConfig`,
+/// or a single type as in older configs
+fn deserialize_outputs<'de, D>(deserializer: D) -> Result<Vec<OutputConfig>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Entry {
+        Type(LogOutput),
+        Config(OutputConfig),
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Outputs {
+        Single(LogOutput),
+        List(Vec<Entry>),
+    }
+
+    Ok(match Outputs::deserialize(deserializer)? {
+        Outputs::Single(output) => single_output(output),
+        Outputs::List(entries) => entries
+            .into_iter()
+            .map(|entry| match entry {
+                Entry::Type(output) => output.into(),
+                Entry::Config(config) => config,
+            })
+            .collect(),
+    })
+}
+
 #[derive(Debug, Deserialize, Default, Clone)]
 #[serde(default)]
 pub struct LogFieldsConfig {
@@ -33,11 +134,28 @@ pub struct DebugConfig {
 
 #[derive(Debug, Deserialize)]
 pub struct LogConfig {
-    pub output: LogOutput,
+    #[serde(alias = "outputs", deserialize_with = "deserialize_outputs")]
+    pub output: Vec<OutputConfig>,
+    /// Level of the outputs that don't set their own
     pub level: String,
+    /// Format of the console and file outputs that don't set their own
+    #[serde(default)]
+    pub format: LogFormat,
     pub server_id: Option<String>, // Add server_id field
     pub file_path: Option<String>,
     pub file_name: Option<String>,
+    #[serde(default)]
+    pub rotation: Rotation,
+    #[serde(default = "default_max_file_size_mb")]
+    pub max_file_size_mb: u64,
+    /// Rotated files to keep, all when unset
+    pub max_files: Option<usize>,
+    /// Remove rotated files older than this
+    pub max_age_secs: Option<u64>,
+    /// Remove the oldest rotated files once they take more than this
+    pub max_total_size_mb: Option<u64>,
+    /// Compress rotated files
+    pub file_compression: Option<Compression>,
     pub grpc: Option<GrpcConfig>,
     #[serde(default)]
     pub server_retry: ServerRetryConfig,
@@ -49,26 +167,128 @@ pub struct LogConfig {
     pub debug_mode: DebugConfig,
     #[serde(default = "default_log_all_messages")]
     pub log_all_messages: bool,
+    #[serde(default = "default_history_size")]
+    pub history_size: usize, // Messages retained for replay to new subscribers
+    #[serde(default)]
+    pub subscriber_queue: QueueConfig,
+}
+
+/// What to do when a subscriber's queue is full
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Discard the oldest queued message to make room
+    DropOldest,
+    /// Discard the incoming message
+    DropNewest,
+    /// Close the client's stream with RESOURCE_EXHAUSTED
+    Disconnect,
+}
+
+/// Bound on the messages waiting to be sent to each subscriber, and the
+/// overflow policy of each client type
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct QueueConfig {
+    pub capacity: usize,
+    pub web_client: OverflowPolicy,
+    pub server: OverflowPolicy,
+    pub unknown: OverflowPolicy,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 10_000,
+            web_client: OverflowPolicy::DropOldest,
+            server: OverflowPolicy::DropOldest,
+            unknown: OverflowPolicy::DropOldest,
+        }
+    }
+}
+
+impl QueueConfig {
+    pub fn policy(&self, client_type: ClientType) -> OverflowPolicy {
+        match client_type {
+            ClientType::WebClient => self.web_client,
+            ClientType::Server => self.server,
+            ClientType::Unknown => self.unknown,
+        }
+    }
 }
 
 fn default_log_all_messages() -> bool {
     false // By default, don't log all messages
 }
 
+fn default_history_size() -> usize {
+    1000
+}
+
+fn default_max_file_size_mb() -> u64 {
+    100
+}
+
+/// File name used when neither the output nor the config sets one
+const DEFAULT_FILE_NAME: &str = "{server_id}.log";
+
+impl LogConfig {
+    /// File name of a file output, with `{server_id}` replaced by the
+    /// sanitized `server_id` ("app" when unset)
+    pub fn file_name(&self, output: &OutputConfig) -> String {
+        let pattern = output
+            .file_name
+            .as_deref()
+            .or(self.file_name.as_deref())
+            .unwrap_or(DEFAULT_FILE_NAME);
+        pattern.replace(
+            "{server_id}",
+            &sanitize_file_name(self.server_id.as_deref().unwrap_or("")),
+        )
+    }
+}
+
+/// Keeps `server_id` inside `file_path`: anything other than ASCII
+/// alphanumerics, `-` and `_` becomes `_`, so no `/` or `..` survives
+fn sanitize_file_name(server_id: &str) -> String {
+    if server_id.is_empty() {
+        return "app".to_string();
+    }
+    server_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
 impl Default for LogConfig {
     fn default() -> Self {
         Self {
-            output: LogOutput::Console, // or whatever default you prefer
+            output: single_output(LogOutput::Console),
             level: "info".to_string(),
+            format: LogFormat::default(),
             server_id: None,
             file_path: None,
             file_name: None,
+            rotation: Rotation::default(),
+            max_file_size_mb: default_max_file_size_mb(),
+            max_files: None,
+            max_age_secs: None,
+            max_total_size_mb: None,
+            file_compression: None,
             grpc: None,
             server_retry: ServerRetryConfig::default(),
             client_retry: ClientRetryConfig::default(),
             log_fields: LogFieldsConfig::default(),
             debug_mode: DebugConfig::default(),
             log_all_messages: false,
+            history_size: default_history_size(),
+            subscriber_queue: QueueConfig::default(),
         }
     }
 }
@@ -88,7 +308,7 @@ pub struct ClientRetryConfig {
     pub reconnect_delay_secs: u64, // For maintaining connection
 }
 
-// Timer formatting
+// Timer formatting, the timestamp of the text format
 pub struct CustomTimer;
 impl FormatTime for CustomTimer {
     fn format_time(&self, w: &mut Writer<'_>) -> std::fmt::Result {
@@ -97,11 +317,18 @@ impl FormatTime for CustomTimer {
     }
 }
 
+/// Span the event was emitted in, with its fields
+struct SpanEntry {
+    name: &'static str,
+    fields: FieldRecorder,
+}
+
 // Custom format struct
 #[derive(Clone)]
 pub struct CustomFormatter {
     pub server_id: Option<String>,
     pub config: LogFieldsConfig,
+    pub format: LogFormat,
 }
 
 impl<S, N> fmt::FormatEvent<S, N> for CustomFormatter
@@ -115,59 +342,255 @@ where
         mut writer: Writer<'_>,
         event: &tracing::Event<'_>,
     ) -> std::fmt::Result {
-        self.write_timestamp(&mut writer)?;
-        self.write_level(&mut writer, event)?;
-        self.write_metadata(&mut writer, event)?;
-        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        let mut recorder = FieldRecorder::default();
+        event.record(&mut recorder);
+
+        // Outermost first
+        let spans: Vec<SpanEntry> = ctx
+            .event_scope()
+            .into_iter()
+            .flat_map(|scope| scope.from_root())
+            .map(|span| SpanEntry {
+                name: span.name(),
+                fields: span
+                    .extensions()
+                    .get::<SpanFields>()
+                    .map(SpanFields::to_recorder)
+                    .unwrap_or_default(),
+            })
+            .collect();
+
+        match self.format {
+            LogFormat::Text => self.write_text(&mut writer, event, &recorder, &spans)?,
+            LogFormat::Compact => self.write_compact(&mut writer, event, &recorder, &spans)?,
+            LogFormat::Logfmt => self.write_logfmt(&mut writer, event, &recorder, &spans)?,
+            LogFormat::Json => self.write_json(&mut writer, event, &recorder, &spans)?,
+        }
         writeln!(writer)
     }
 }
 
 impl CustomFormatter {
-    fn write_timestamp(&self, writer: &mut Writer<'_>) -> std::fmt::Result {
-        let time = chrono::Local::now();
-        write!(writer, "[{}] ", time.format("%Y-%m-%d %H:%M:%S"))
+    fn timestamp(&self) -> Option<chrono::DateTime<chrono::Local>> {
+        self.config
+            .include_timestamp
+            .then(chrono::Local::now)
+    }
+
+    fn target<'a>(&self, event: &tracing::Event<'a>) -> Option<&'a str> {
+        let target = event.metadata().target();
+        (self.config.include_target && target != "tokio_util::codec::framed_impl")
+            .then_some(target)
+    }
+
+    fn file<'a>(&self, event: &tracing::Event<'a>) -> Option<&'a str> {
+        event.metadata().file().filter(|_| self.config.include_file)
     }
 
-    fn write_level(&self, writer: &mut Writer<'_>, event: &tracing::Event<'_>) -> std::fmt::Result {
-        write!(writer, "{:>5} ", event.metadata().level())
+    fn line(&self, event: &tracing::Event<'_>) -> Option<u32> {
+        event.metadata().line().filter(|_| self.config.include_line)
     }
 
-    fn write_metadata(
+    fn thread_id(&self) -> Option<String> {
+        self.config
+            .include_thread_id
+            .then(|| format!("{:?}", std::thread::current().id()))
+    }
+
+    /// `[2025-01-31 12:00:00]  INFO [server] target - file:line outer{a=1}:inner: message k=v`,
+    /// always timestamped as it was before the other formats existed
+    fn write_text(
         &self,
         writer: &mut Writer<'_>,
         event: &tracing::Event<'_>,
+        recorder: &FieldRecorder,
+        spans: &[SpanEntry],
     ) -> std::fmt::Result {
+        CustomTimer.format_time(writer)?;
+        writer.write_char(' ')?;
+        write!(writer, "{:>5} ", event.metadata().level())?;
         if let Some(server_id) = &self.server_id {
             write!(writer, "[{}] ", server_id)?;
         }
+        if let Some(target) = self.target(event) {
+            write!(writer, "{} - ", target)?;
+        }
+        match (self.file(event), self.line(event)) {
+            (Some(file), Some(line)) => write!(writer, "{}:{} ", file, line)?,
+            (Some(file), None) => write!(writer, "{} ", file)?,
+            _ => {}
+        }
+        if let Some(thread_id) = self.thread_id() {
+            write!(writer, "{} ", thread_id)?;
+        }
+        for span in spans {
+            if span.fields.fields.is_empty() {
+                write!(writer, "{}:", span.name)?;
+            } else {
+                write!(writer, "{}{{", span.name)?;
+                span.fields.write_pairs(writer, "")?;
+                writer.write_str("}:")?;
+            }
+        }
+        if !spans.is_empty() {
+            writer.write_char(' ')?;
+        }
+        recorder.write_text(writer)
+    }
 
-        if self.config.include_target
-            && event.metadata().target() != "tokio_util::codec::framed_impl"
-        {
-            write!(writer, "{} - ", event.metadata().target())?;
+    /// `12:00:00 INFO [server] outer:inner: message k=v a=1`, span fields
+    /// after the event's
+    fn write_compact(
+        &self,
+        writer: &mut Writer<'_>,
+        event: &tracing::Event<'_>,
+        recorder: &FieldRecorder,
+        spans: &[SpanEntry],
+    ) -> std::fmt::Result {
+        if let Some(time) = self.timestamp() {
+            write!(writer, "{} ", time.format("%H:%M:%S%.3f"))?;
+        }
+        write!(writer, "{} ", event.metadata().level())?;
+        if let Some(server_id) = &self.server_id {
+            write!(writer, "[{}] ", server_id)?;
+        }
+        if let Some(target) = self.target(event) {
+            write!(writer, "{}", target)?;
+            if let Some(line) = self.line(event) {
+                write!(writer, ":{}", line)?;
+            }
+            writer.write_str(": ")?;
+        }
+        if let Some(thread_id) = self.thread_id() {
+            write!(writer, "{} ", thread_id)?;
         }
+        for span in spans {
+            write!(writer, "{}:", span.name)?;
+        }
+        if !spans.is_empty() {
+            writer.write_char(' ')?;
+        }
+        recorder.write_text(writer)?;
+        for span in spans {
+            span.fields.write_pairs(writer, " ")?;
+        }
+        Ok(())
+    }
+
+    /// `ts=... level=info server_id=... span=outer:inner msg=... k=v a=1`,
+    /// span fields after the event's
+    fn write_logfmt(
+        &self,
+        writer: &mut Writer<'_>,
+        event: &tracing::Event<'_>,
+        recorder: &FieldRecorder,
+        spans: &[SpanEntry],
+    ) -> std::fmt::Result {
+        let quoted = |value: &str| FieldValue::from(Value::StringValue(value.to_string()));
 
-        // Additional metadata fields...
-        self.write_location_info(writer, event)
+        let mut separator = "";
+        if let Some(time) = self.timestamp() {
+            write!(writer, "ts={}", time.to_rfc3339())?;
+            separator = " ";
+        }
+        write!(
+            writer,
+            "{}level={}",
+            separator,
+            event.metadata().level().as_str().to_lowercase()
+        )?;
+        if let Some(server_id) = &self.server_id {
+            write!(writer, " server_id={}", quoted(server_id))?;
+        }
+        if let Some(target) = self.target(event) {
+            write!(writer, " target={}", quoted(target))?;
+        }
+        if let Some(file) = self.file(event) {
+            write!(writer, " file={}", quoted(file))?;
+        }
+        if let Some(line) = self.line(event) {
+            write!(writer, " line={}", line)?;
+        }
+        if let Some(thread_id) = self.thread_id() {
+            write!(writer, " thread_id={}", quoted(&thread_id))?;
+        }
+        if !spans.is_empty() {
+            let names: Vec<&str> = spans.iter().map(|span| span.name).collect();
+            write!(writer, " span={}", quoted(&names.join(":")))?;
+        }
+        write!(
+            writer,
+            " msg={}",
+            quoted(recorder.message.as_deref().unwrap_or_default())
+        )?;
+        recorder.write_pairs(writer, " ")?;
+        for span in spans {
+            span.fields.write_pairs(writer, " ")?;
+        }
+        Ok(())
     }
 
-    fn write_location_info(
+    /// One object per line, with the event's fields under `fields` and the
+    /// spans, outermost first, under `spans`
+    fn write_json(
         &self,
         writer: &mut Writer<'_>,
         event: &tracing::Event<'_>,
+        recorder: &FieldRecorder,
+        spans: &[SpanEntry],
     ) -> std::fmt::Result {
-        if self.config.include_file {
-            if let Some(file) = event.metadata().file() {
-                write!(writer, "{}:", file)?;
-                if self.config.include_line {
-                    if let Some(line) = event.metadata().line() {
-                        write!(writer, "{} ", line)?;
-                    }
+        writer.write_char('{')?;
+        if let Some(time) = self.timestamp() {
+            writer.write_str("\"timestamp\":")?;
+            write_json_str(writer, &time.to_rfc3339())?;
+            writer.write_char(',')?;
+        }
+        write!(writer, "\"level\":\"{}\"", event.metadata().level())?;
+        if let Some(server_id) = &self.server_id {
+            writer.write_str(",\"server_id\":")?;
+            write_json_str(writer, server_id)?;
+        }
+        if let Some(target) = self.target(event) {
+            writer.write_str(",\"target\":")?;
+            write_json_str(writer, target)?;
+        }
+        if let Some(file) = self.file(event) {
+            writer.write_str(",\"file\":")?;
+            write_json_str(writer, file)?;
+        }
+        if let Some(line) = self.line(event) {
+            write!(writer, ",\"line\":{}", line)?;
+        }
+        if let Some(thread_id) = self.thread_id() {
+            writer.write_str(",\"thread_id\":")?;
+            write_json_str(writer, &thread_id)?;
+        }
+        writer.write_str(",\"message\":")?;
+        write_json_str(writer, recorder.message.as_deref().unwrap_or_default())?;
+
+        writer.write_str(",\"fields\":{")?;
+        let fields = FieldRecorder {
+            message: None,
+            fields: recorder.fields.clone(),
+        };
+        fields.write_json_members(writer)?;
+        writer.write_char('}')?;
+
+        if !spans.is_empty() {
+            writer.write_str(",\"spans\":[")?;
+            for (i, span) in spans.iter().enumerate() {
+                if i > 0 {
+                    writer.write_char(',')?;
                 }
+                writer.write_str("{\"name\":")?;
+                write_json_str(writer, span.name)?;
+                writer.write_str(",\"fields\":{")?;
+                span.fields.write_json_members(writer)?;
+                writer.write_str("}}")?;
             }
+            writer.write_char(']')?;
         }
-        Ok(())
+        writer.write_char('}')
     }
 }
 
@@ -179,3 +602,208 @@ pub fn load_config(path: &str) -> Result<LogConfig, Box<dyn std::error::Error +
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fields::{FieldsFormatter, SpanFieldsLayer};
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::Registry;
+
+    /// Output of the formatter, shared with the test
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Lines written by `format` for the events logged by `log`
+    fn format_lines(format: LogFormat, config: LogFieldsConfig, log: impl FnOnce()) -> Vec<String> {
+        let capture = Capture::default();
+        let writer = capture.clone();
+        let subscriber = Registry::default().with(SpanFieldsLayer).with(
+            fmt::layer()
+                .event_format(CustomFormatter {
+                    server_id: Some("DB Webservice".to_string()),
+                    config,
+                    format,
+                })
+                .fmt_fields(FieldsFormatter)
+                .with_writer(move || writer.clone()),
+        );
+        tracing::subscriber::with_default(subscriber, log);
+
+        let output = String::from_utf8(capture.0.lock().unwrap().clone()).unwrap();
+        output.lines().map(str::to_string).collect()
+    }
+
+    fn outputs(yaml: &str) -> Vec<OutputConfig> {
+        let config: LogConfig = serde_yaml::from_str(&format!("level: info\n{}", yaml)).unwrap();
+        config.output
+    }
+
+    fn types(outputs: &[OutputConfig]) -> Vec<LogOutput> {
+        outputs.iter().map(|output| output.output).collect()
+    }
+
+    #[test]
+    fn single_value_keeps_broadcasting() {
+        assert_eq!(
+            types(&outputs("output: console")),
+            [LogOutput::Console, LogOutput::Grpc]
+        );
+        assert_eq!(
+            types(&outputs("output: grpc")),
+            [LogOutput::Console, LogOutput::Grpc]
+        );
+        assert_eq!(
+            types(&outputs("output: file")),
+            [LogOutput::File, LogOutput::Grpc]
+        );
+    }
+
+    #[test]
+    fn list_of_types() {
+        let outputs = outputs("output: [file, console]");
+        assert_eq!(types(&outputs), [LogOutput::File, LogOutput::Console]);
+        assert!(outputs.iter().all(|output| output.level.is_none()));
+    }
+
+    #[test]
+    fn list_of_types_and_maps() {
+        let outputs = outputs(
+            r#"
+outputs:
+  - console
+  - type: file
+    level: debug
+    format: json
+    file_path: /var/log/app
+    file_name: app.log
+    log_fields:
+      include_line: true
+"#,
+        );
+        assert_eq!(types(&outputs), [LogOutput::Console, LogOutput::File]);
+        assert!(outputs[0].level.is_none());
+
+        let file = &outputs[1];
+        assert_eq!(file.level.as_deref(), Some("debug"));
+        assert_eq!(file.format, Some(LogFormat::Json));
+        assert_eq!(file.file_path.as_deref(), Some("/var/log/app"));
+        assert_eq!(file.file_name.as_deref(), Some("app.log"));
+        assert!(file.log_fields.as_ref().is_some_and(|fields| fields.include_line));
+    }
+
+    #[test]
+    fn file_name_defaults_to_server_id() {
+        let output = OutputConfig::from(LogOutput::File);
+        let mut config = LogConfig::default();
+        assert_eq!(config.file_name(&output), "app.log");
+
+        config.server_id = Some("db-webservice".to_string());
+        assert_eq!(config.file_name(&output), "db-webservice.log");
+
+        config.file_name = Some("{server_id}-access.log".to_string());
+        assert_eq!(config.file_name(&output), "db-webservice-access.log");
+
+        let output = OutputConfig {
+            file_name: Some("audit.log".to_string()),
+            ..OutputConfig::from(LogOutput::File)
+        };
+        assert_eq!(config.file_name(&output), "audit.log");
+    }
+
+    #[test]
+    fn file_name_sanitizes_server_id() {
+        let output = OutputConfig::from(LogOutput::File);
+        let mut config = LogConfig {
+            server_id: Some("../etc/passwd".to_string()),
+            ..LogConfig::default()
+        };
+        assert_eq!(config.file_name(&output), "___etc_passwd.log");
+
+        config.file_name = Some("{server_id}".to_string());
+        config.server_id = Some("..".to_string());
+        assert_eq!(config.file_name(&output), "__");
+        config.server_id = Some(String::new());
+        assert_eq!(config.file_name(&output), "app");
+    }
+
+    #[test]
+    fn rejects_unknown_types() {
+        let config = serde_yaml::from_str::<LogConfig>("level: info\noutput: syslog");
+        assert!(config.is_err());
+        let config = serde_yaml::from_str::<LogConfig>("level: info\noutput:\n  - type: syslog");
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn json_writes_one_object_per_line() {
+        let config = LogFieldsConfig {
+            include_target: true,
+            include_line: true,
+            include_timestamp: true,
+            ..LogFieldsConfig::default()
+        };
+        let lines = format_lines(LogFormat::Json, config, || {
+            let span = tracing::info_span!("request", id = 42, path = "/a\"b");
+            let _enter = span.enter();
+            tracing::info!(
+                rows = 3,
+                ratio = 0.5,
+                cached = true,
+                "multi\nline\t\"quoted\" \u{1}"
+            );
+            tracing::warn!("second");
+        });
+        assert_eq!(lines.len(), 2);
+
+        let event: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(event["level"], "INFO");
+        assert_eq!(event["server_id"], "DB Webservice");
+        assert_eq!(event["target"], module_path!());
+        assert!(event["line"].is_u64());
+        assert!(event["timestamp"].is_string());
+        assert_eq!(event["message"], "multi\nline\t\"quoted\" \u{1}");
+        assert_eq!(event["fields"]["rows"], 3);
+        assert_eq!(event["fields"]["ratio"], 0.5);
+        assert_eq!(event["fields"]["cached"], true);
+        assert_eq!(event["spans"][0]["name"], "request");
+        assert_eq!(event["spans"][0]["fields"]["id"], 42);
+        assert_eq!(event["spans"][0]["fields"]["path"], "/a\"b");
+
+        let event: serde_json::Value = serde_json::from_str(&lines[1]).unwrap();
+        assert_eq!(event["level"], "WARN");
+        assert_eq!(event["message"], "second");
+    }
+
+    #[test]
+    fn logfmt_quotes_values_that_are_not_single_tokens() {
+        let lines = format_lines(LogFormat::Logfmt, LogFieldsConfig::default(), || {
+            tracing::info!(
+                user = "jane doe",
+                query = "a=b",
+                empty = "",
+                note = "two\nlines",
+                plain = "x",
+                count = 2,
+                "say \"hi\""
+            );
+        });
+        assert_eq!(
+            lines,
+            [r#"level=info server_id="DB Webservice" msg="say \"hi\"" user="jane doe" query="a=b" empty="" note="two\nlines" plain=x count=2"#]
+        );
+    }
+}
@@ -0,0 +1,45 @@
+use crate::server_build::logging::v2::Level;
+use chrono::{DateTime, FixedOffset, Utc};
+use prost_types::Timestamp;
+
+impl From<tracing::Level> for Level {
+    fn from(level: tracing::Level) -> Self {
+        match level {
+            tracing::Level::TRACE => Level::Trace,
+            tracing::Level::DEBUG => Level::Debug,
+            tracing::Level::INFO => Level::Info,
+            tracing::Level::WARN => Level::Warn,
+            tracing::Level::ERROR => Level::Error,
+        }
+    }
+}
+
+impl Level {
+    pub fn to_tracing(self) -> Option<tracing::Level> {
+        match self {
+            Level::Unspecified => None,
+            Level::Trace => Some(tracing::Level::TRACE),
+            Level::Debug => Some(tracing::Level::DEBUG),
+            Level::Info => Some(tracing::Level::INFO),
+            Level::Warn => Some(tracing::Level::WARN),
+            Level::Error => Some(tracing::Level::ERROR),
+        }
+    }
+}
+
+pub fn to_timestamp(time: DateTime<Utc>) -> Timestamp {
+    Timestamp {
+        seconds: time.timestamp(),
+        nanos: time.timestamp_subsec_nanos() as i32,
+    }
+}
+
+pub fn from_timestamp(timestamp: &Timestamp) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp(timestamp.seconds, timestamp.nanos.try_into().ok()?)
+}
+
+/// The time as seen by the emitting process, for display
+pub fn to_origin_time(timestamp: &Timestamp, utc_offset_secs: i32) -> Option<DateTime<FixedOffset>> {
+    let offset = FixedOffset::east_opt(utc_offset_secs)?;
+    Some(from_timestamp(timestamp)?.with_timezone(&offset))
+}
@@ -0,0 +1,232 @@
+use crate::server_build::logging::v2::field_value::Value;
+use crate::server_build::logging::v2::FieldValue;
+use std::fmt;
+use tracing::field::{Field, Visit};
+use tracing::span;
+use tracing_subscriber::field::RecordFields;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::FormatFields;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::{ExtensionsMut, LookupSpan};
+use tracing_subscriber::Layer;
+
+impl From<Value> for FieldValue {
+    fn from(value: Value) -> Self {
+        Self { value: Some(value) }
+    }
+}
+
+impl fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(Value::StringValue(s)) => {
+                // Quote only when the value wouldn't read back as a single token
+                if s.is_empty() || s.contains(|c: char| c.is_whitespace() || c == '=' || c == '"') {
+                    write!(f, "{:?}", s)
+                } else {
+                    f.write_str(s)
+                }
+            }
+            Some(Value::I64Value(v)) => write!(f, "{}", v),
+            Some(Value::U64Value(v)) => write!(f, "{}", v),
+            Some(Value::F64Value(v)) => write!(f, "{}", v),
+            Some(Value::BoolValue(v)) => write!(f, "{}", v),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Records event or span fields as typed values, keeping `message` apart.
+///
+/// Fields are kept in the order they were recorded.
+#[derive(Debug, Default)]
+pub struct FieldRecorder {
+    pub message: Option<String>,
+    pub fields: Vec<(String, FieldValue)>,
+}
+
+impl FieldRecorder {
+    fn record_value(&mut self, field: &Field, value: Value) {
+        self.fields.push((field.name().to_string(), value.into()));
+    }
+
+    /// The message followed by `key=value` pairs
+    pub(crate) fn write_text(&self, writer: &mut Writer<'_>) -> fmt::Result {
+        let mut separator = "";
+        if let Some(message) = &self.message {
+            write!(writer, "{}", message)?;
+            separator = " ";
+        }
+        self.write_pairs(writer, separator)
+    }
+
+    /// The fields as `key=value` pairs, the first one preceded by `separator`
+    pub(crate) fn write_pairs(&self, writer: &mut Writer<'_>, mut separator: &str) -> fmt::Result {
+        for (name, value) in &self.fields {
+            write!(writer, "{}{}={}", separator, name, value)?;
+            separator = " ";
+        }
+        Ok(())
+    }
+
+    /// The message and fields as comma-separated JSON members, without braces
+    pub(crate) fn write_json_members(&self, writer: &mut Writer<'_>) -> fmt::Result {
+        let mut separator = "";
+        if let Some(message) = &self.message {
+            write_json_str(writer, "message")?;
+            writer.write_char(':')?;
+            write_json_str(writer, message)?;
+            separator = ",";
+        }
+        for (name, value) in &self.fields {
+            writer.write_str(separator)?;
+            write_json_str(writer, name)?;
+            writer.write_char(':')?;
+            write_json_value(writer, value)?;
+            separator = ",";
+        }
+        Ok(())
+    }
+}
+
+impl Visit for FieldRecorder {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.record_value(field, Value::I64Value(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.record_value(field, Value::U64Value(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.record_value(field, Value::F64Value(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.record_value(field, Value::BoolValue(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = Some(value.to_string());
+        } else {
+            self.record_value(field, Value::StringValue(value.to_string()));
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = Some(format!("{:?}", value));
+        } else {
+            self.record_value(field, Value::StringValue(format!("{:?}", value)));
+        }
+    }
+}
+
+/// Field formatter for the console and file outputs.
+///
+/// Writes the message followed by `key=value` pairs rendered the same way as
+/// the `FieldValue`s sent to gRPC subscribers.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FieldsFormatter;
+
+impl<'writer> FormatFields<'writer> for FieldsFormatter {
+    fn format_fields<R: RecordFields>(&self, mut writer: Writer<'writer>, fields: R) -> fmt::Result {
+        let mut recorder = FieldRecorder::default();
+        fields.record(&mut recorder);
+        recorder.write_text(&mut writer)
+    }
+}
+
+/// Fields recorded on a span, stored in the span's extensions. A field
+/// recorded again replaces its previous value.
+#[derive(Debug, Default)]
+pub(crate) struct SpanFields(pub(crate) Vec<(String, FieldValue)>);
+
+impl SpanFields {
+    pub(crate) fn merge(&mut self, recorded: FieldRecorder) {
+        for (name, value) in recorded.fields {
+            match self.0.iter_mut().find(|(existing, _)| *existing == name) {
+                Some((_, existing)) => *existing = value,
+                None => self.0.push((name, value)),
+            }
+        }
+    }
+
+    /// Merge `recorded` into the fields of the span owning `extensions`
+    pub(crate) fn record(extensions: &mut ExtensionsMut<'_>, recorded: FieldRecorder) {
+        match extensions.get_mut::<SpanFields>() {
+            Some(fields) => fields.merge(recorded),
+            None => {
+                let mut fields = SpanFields::default();
+                fields.merge(recorded);
+                extensions.insert(fields);
+            }
+        }
+    }
+
+    /// The fields as a recorder, to be written like an event's
+    pub(crate) fn to_recorder(&self) -> FieldRecorder {
+        FieldRecorder {
+            message: None,
+            fields: self.0.clone(),
+        }
+    }
+}
+
+/// Records span fields into `SpanFields`, once for all the console and file
+/// outputs. The fields each `fmt` layer formats itself would be appended to
+/// by every layer on `Span::record`.
+pub(crate) struct SpanFieldsLayer;
+
+impl<S> Layer<S> for SpanFieldsLayer
+where
+    S: tracing::Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let mut recorder = FieldRecorder::default();
+            attrs.record(&mut recorder);
+            SpanFields::record(&mut span.extensions_mut(), recorder);
+        }
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let mut recorder = FieldRecorder::default();
+            values.record(&mut recorder);
+            SpanFields::record(&mut span.extensions_mut(), recorder);
+        }
+    }
+}
+
+/// JSON string literal
+pub(crate) fn write_json_str(writer: &mut Writer<'_>, value: &str) -> fmt::Result {
+    writer.write_char('"')?;
+    for c in value.chars() {
+        match c {
+            '"' => writer.write_str("\\\"")?,
+            '\\' => writer.write_str("\\\\")?,
+            '\n' => writer.write_str("\\n")?,
+            '\r' => writer.write_str("\\r")?,
+            '\t' => writer.write_str("\\t")?,
+            c if c.is_control() => write!(writer, "\\u{:04x}", c as u32)?,
+            c => writer.write_char(c)?,
+        }
+    }
+    writer.write_char('"')
+}
+
+/// JSON value of a field, keeping its type. Non-finite floats, which JSON
+/// can't represent, are written as strings.
+pub(crate) fn write_json_value(writer: &mut Writer<'_>, value: &FieldValue) -> fmt::Result {
+    match &value.value {
+        Some(Value::StringValue(v)) => write_json_str(writer, v),
+        Some(Value::I64Value(v)) => write!(writer, "{}", v),
+        Some(Value::U64Value(v)) => write!(writer, "{}", v),
+        Some(Value::F64Value(v)) if v.is_finite() => write!(writer, "{}", v),
+        Some(Value::F64Value(v)) => write_json_str(writer, &v.to_string()),
+        Some(Value::BoolValue(v)) => write!(writer, "{}", v),
+        None => writer.write_str("null"),
+    }
+}
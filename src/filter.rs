@@ -0,0 +1,174 @@
+use crate::server_build::logging::v2::{Level, LogFilter, LogMessage};
+use tonic::Status;
+
+/// Filter applied by the server to each message before it reaches a subscriber.
+///
+/// Built from the `LogFilter` carried by `SubscribeRequest`. Empty lists mean
+/// "no restriction".
+#[derive(Debug, Clone, Default)]
+pub struct SubscriptionFilter {
+    min_level: Option<Level>,
+    include_targets: Vec<String>,
+    exclude_targets: Vec<String>,
+    server_ids: Vec<String>,
+}
+
+impl SubscriptionFilter {
+    pub fn from_proto(filter: Option<LogFilter>) -> Result<Self, Status> {
+        let Some(filter) = filter else {
+            return Ok(Self::default());
+        };
+
+        let min_level = match Level::try_from(filter.min_level) {
+            Ok(Level::Unspecified) => None,
+            Ok(level) => Some(level),
+            Err(_) => {
+                return Err(Status::invalid_argument(format!(
+                    "Invalid min_level: {}",
+                    filter.min_level
+                )))
+            }
+        };
+
+        Ok(Self {
+            min_level,
+            include_targets: filter.include_targets,
+            exclude_targets: filter.exclude_targets,
+            server_ids: filter.server_ids,
+        })
+    }
+
+    /// Returns true if the message should be delivered to the subscriber.
+    pub fn matches(&self, log: &LogMessage) -> bool {
+        if let Some(min_level) = self.min_level {
+            // Messages without a level are never dropped for it
+            if log.level != Level::Unspecified as i32 && log.level < min_level as i32 {
+                return false;
+            }
+        }
+
+        // Messages without a target can't match an include list
+        match log.target.as_deref() {
+            Some(target) => {
+                if self.exclude_targets.iter().any(|p| target.starts_with(p.as_str())) {
+                    return false;
+                }
+                if !self.include_targets.is_empty()
+                    && !self.include_targets.iter().any(|p| target.starts_with(p.as_str()))
+                {
+                    return false;
+                }
+            }
+            None if !self.include_targets.is_empty() => return false,
+            None => {}
+        }
+
+        if !self.server_ids.is_empty() {
+            match log.server_id.as_deref() {
+                Some(server_id) => {
+                    if !self.server_ids.iter().any(|id| id == server_id) {
+                        return false;
+                    }
+                }
+                None => return false,
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log(level: Level, target: Option<&str>, server_id: Option<&str>) -> LogMessage {
+        LogMessage {
+            level: level as i32,
+            target: target.map(str::to_string),
+            server_id: server_id.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    fn filter(
+        min_level: Level,
+        include_targets: &[&str],
+        exclude_targets: &[&str],
+        server_ids: &[&str],
+    ) -> SubscriptionFilter {
+        let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect();
+        SubscriptionFilter::from_proto(Some(LogFilter {
+            min_level: min_level as i32,
+            include_targets: strings(include_targets),
+            exclude_targets: strings(exclude_targets),
+            server_ids: strings(server_ids),
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn no_filter_matches_everything() {
+        let filter = SubscriptionFilter::from_proto(None).unwrap();
+        assert!(filter.matches(&log(Level::Trace, None, None)));
+        assert!(filter.matches(&log(Level::Error, Some("app"), Some("a"))));
+    }
+
+    #[test]
+    fn rejects_unknown_min_level() {
+        let filter = SubscriptionFilter::from_proto(Some(LogFilter {
+            min_level: 42,
+            ..Default::default()
+        }));
+        assert_eq!(filter.unwrap_err().code(), tonic::Code::InvalidArgument);
+    }
+
+    #[test]
+    fn drops_messages_below_min_level() {
+        let filter = filter(Level::Warn, &[], &[], &[]);
+        assert!(!filter.matches(&log(Level::Info, None, None)));
+        assert!(filter.matches(&log(Level::Warn, None, None)));
+        assert!(filter.matches(&log(Level::Error, None, None)));
+        // No level, nothing to compare with
+        assert!(filter.matches(&log(Level::Unspecified, None, None)));
+    }
+
+    #[test]
+    fn include_and_exclude_targets_by_prefix() {
+        let app = filter(Level::Unspecified, &["app"], &["app::db"], &[]);
+        assert!(app.matches(&log(Level::Info, Some("app::http"), None)));
+        assert!(!app.matches(&log(Level::Info, Some("app::db::pool"), None)));
+        assert!(!app.matches(&log(Level::Info, Some("hyper"), None)));
+
+        let no_hyper = filter(Level::Unspecified, &[], &["hyper"], &[]);
+        assert!(!no_hyper.matches(&log(Level::Info, Some("hyper::proto"), None)));
+        assert!(no_hyper.matches(&log(Level::Info, Some("app"), None)));
+    }
+
+    #[test]
+    fn messages_without_target() {
+        let excluding = filter(Level::Unspecified, &[], &["hyper"], &[]);
+        assert!(excluding.matches(&log(Level::Info, None, None)));
+
+        let including = filter(Level::Unspecified, &["app"], &[], &[]);
+        assert!(!including.matches(&log(Level::Info, None, None)));
+    }
+
+    #[test]
+    fn server_ids() {
+        let filter = filter(Level::Unspecified, &[], &[], &["a", "b"]);
+        assert!(filter.matches(&log(Level::Info, None, Some("a"))));
+        assert!(filter.matches(&log(Level::Info, None, Some("b"))));
+        assert!(!filter.matches(&log(Level::Info, None, Some("c"))));
+        assert!(!filter.matches(&log(Level::Info, None, None)));
+    }
+
+    #[test]
+    fn every_criterion_must_match() {
+        let filter = filter(Level::Info, &["app"], &[], &["a"]);
+        assert!(filter.matches(&log(Level::Info, Some("app"), Some("a"))));
+        assert!(!filter.matches(&log(Level::Debug, Some("app"), Some("a"))));
+        assert!(!filter.matches(&log(Level::Info, Some("lib"), Some("a"))));
+        assert!(!filter.matches(&log(Level::Info, Some("app"), Some("b"))));
+    }
+}
@@ -0,0 +1,201 @@
+use super::{CompressionConfig, GrpcConfig};
+use crate::config::ClientRetryConfig;
+use crate::runtime;
+use crate::server_build::logging::v2::log_service_client::LogServiceClient;
+use crate::convert::to_timestamp;
+use crate::server_build::logging::v2::field_value::Value;
+use crate::server_build::logging::v2::{Level, LogMessage};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::transport::Endpoint;
+use tokio::time::{sleep, Duration};
+
+/// Ships log messages to a remote logger through the `PushLogs` RPC.
+///
+/// Messages are queued and sent by a background task that reconnects with
+/// exponential backoff when the remote logger goes away. Messages emitted while
+/// disconnected are buffered until the next connection, up to
+/// `GrpcConfig::forward_buffer`: beyond it new messages are dropped, counted,
+/// and reported to the remote logger once it is reachable again. Messages
+/// already handed to a push stream when it fails, at most what the
+/// connection had buffered, are lost.
+#[derive(Debug, Clone)]
+pub struct LogForwarder {
+    sender: mpsc::Sender<LogMessage>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl LogForwarder {
+    /// Spawn the forwarding task for the logger described by `grpc`, on the
+    /// current Tokio runtime or, outside of one, on a thread of its own.
+    pub fn connect(
+        grpc: &GrpcConfig,
+        retry: &ClientRetryConfig,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let endpoint = grpc.endpoint()?;
+        let authorization = grpc
+            .auth
+            .as_ref()
+            .and_then(|auth| auth.client_token.as_deref())
+            .map(|token| MetadataValue::try_from(format!("Bearer {}", token)))
+            .transpose()?;
+
+        let (sender, receiver) = mpsc::channel(grpc.forward_buffer.max(1));
+        let dropped = Arc::new(AtomicU64::new(0));
+        let base_delay = Duration::from_secs(retry.base_delay_secs.max(1));
+        let max_retries = retry.max_retries;
+        runtime::spawn(
+            "grpc-logger-forward",
+            run(
+                endpoint,
+                receiver,
+                dropped.clone(),
+                base_delay,
+                max_retries,
+                grpc.compression.clone(),
+                authorization,
+            ),
+        );
+        Ok(Self { sender, dropped })
+    }
+
+    pub fn send(&self, log: LogMessage) {
+        match self.sender.try_send(log) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            // The task only goes away once retries are exhausted
+            Err(mpsc::error::TrySendError::Closed(_)) => {}
+        }
+    }
+
+    /// Messages dropped so far because the buffer was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Messages of one push stream, pulled from the forwarder's buffer as the
+/// stream sends them, so what isn't sent yet stays buffered if it fails
+struct Outgoing {
+    receiver: Arc<Mutex<mpsc::Receiver<LogMessage>>>,
+    dropped: Arc<AtomicU64>,
+    // Dropped messages already reported to the remote logger
+    reported: Arc<AtomicU64>,
+    // Set once every sender is gone and the buffer is drained
+    finished: Arc<AtomicBool>,
+}
+
+impl Outgoing {
+    async fn next(&self) -> Option<LogMessage> {
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        let reported = self.reported.swap(dropped, Ordering::Relaxed);
+        if dropped > reported {
+            return Some(dropped_message(dropped - reported));
+        }
+        let log = self.receiver.lock().await.recv().await;
+        if log.is_none() {
+            self.finished.store(true, Ordering::Relaxed);
+        }
+        log
+    }
+}
+
+/// Message telling the remote logger that messages were dropped while it
+/// could not be reached
+fn dropped_message(count: u64) -> LogMessage {
+    let now = chrono::Local::now();
+    LogMessage {
+        timestamp: Some(to_timestamp(now.to_utc())),
+        utc_offset_secs: now.offset().local_minus_utc(),
+        level: Level::Warn as i32,
+        message: format!(
+            "{} messages dropped, the forwarding buffer was full while the logger was unreachable",
+            count
+        ),
+        fields: [("dropped".to_string(), Value::U64Value(count).into())].into(),
+        ..Default::default()
+    }
+}
+
+async fn run(
+    endpoint: Endpoint,
+    receiver: mpsc::Receiver<LogMessage>,
+    dropped: Arc<AtomicU64>,
+    base_delay: Duration,
+    max_retries: u32,
+    compression: CompressionConfig,
+    authorization: Option<MetadataValue<Ascii>>,
+) {
+    let addr = endpoint.uri().to_string();
+    let receiver = Arc::new(Mutex::new(receiver));
+    let reported = Arc::new(AtomicU64::new(0));
+    let finished = Arc::new(AtomicBool::new(false));
+    let mut retry_count = 0;
+    loop {
+        let channel = endpoint.connect().await;
+        match channel {
+            Ok(channel) => {
+                let authorization = authorization.clone();
+                let mut client = LogServiceClient::with_interceptor(
+                    channel,
+                    move |mut request: tonic::Request<()>| {
+                        if let Some(authorization) = &authorization {
+                            request
+                                .metadata_mut()
+                                .insert("authorization", authorization.clone());
+                        }
+                        Ok(request)
+                    },
+                );
+                if let Some(&encoding) = compression.send.first() {
+                    client = client.send_compressed(encoding.into());
+                }
+                for &encoding in &compression.accept {
+                    client = client.accept_compressed(encoding.into());
+                }
+                println!("GRPC Logger: Forwarding logs to {}", addr);
+                retry_count = 0;
+
+                let outgoing = Outgoing {
+                    receiver: receiver.clone(),
+                    dropped: dropped.clone(),
+                    reported: reported.clone(),
+                    finished: finished.clone(),
+                };
+                let stream = futures::stream::unfold(outgoing, |outgoing| async move {
+                    let log = outgoing.next().await?;
+                    Some((log, outgoing))
+                });
+                match client.push_logs(stream).await {
+                    Ok(response) => println!(
+                        "GRPC Logger: Push stream closed after {} messages",
+                        response.into_inner().accepted
+                    ),
+                    Err(e) => println!("GRPC Logger Error: Push stream failed: {}", e),
+                }
+                // All senders dropped and what they sent was flushed
+                if finished.load(Ordering::Relaxed) {
+                    return;
+                }
+            }
+            Err(e) => {
+                retry_count += 1;
+                if max_retries > 0 && retry_count > max_retries {
+                    println!(
+                        "GRPC Logger Error: Giving up on {} after {} retries",
+                        addr, max_retries
+                    );
+                    return;
+                }
+                println!("GRPC Logger Error: Failed to connect to {}: {}", addr, e);
+            }
+        }
+
+        let delay = base_delay.mul_f32(1.5f32.powi(retry_count.min(10) as i32));
+        sleep(delay).await;
+    }
+}
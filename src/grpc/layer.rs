@@ -1,23 +1,166 @@
+use super::trace::{TraceContext, TRACEPARENT};
+use super::LogForwarder;
 use crate::config::LogFieldsConfig;
-use crate::server_build::logging::LogMessage;
+use crate::fields::{FieldRecorder, SpanFields};
+use crate::convert::to_timestamp;
+use crate::server_build::logging::v2::field_value::Value;
+use crate::server_build::logging::v2::{Level, LogMessage, SpanContext};
 use crate::server_build::LoggingService;
 use tracing::field::{Field, Visit};
+use tracing::span;
+use tracing::subscriber::Interest;
+use tracing::Metadata;
+use tracing_subscriber::filter::{Filtered, LevelFilter};
+use tracing_subscriber::layer::{Context, Filter};
+use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::Layer;
 
+/// Where a `GrpcLayer` delivers the messages it builds
+#[derive(Clone)]
+pub enum GrpcSink {
+    /// Broadcast to the subscribers of a logging service running in this process
+    Local(LoggingService),
+    /// Push to a remote logging service through its `PushLogs` RPC
+    Forward(LogForwarder),
+}
+
 pub struct GrpcLayer {
-    pub service: LoggingService,
+    pub sink: GrpcSink,
     pub config: LogFieldsConfig,
     pub server_id: Option<String>,
 }
 
+impl GrpcLayer {
+    /// This layer, sending the events `filter` enables, e.g. a `LevelFilter`
+    /// or an `EnvFilter`. Use it rather than `Layer::with_filter`, which would
+    /// also hide the spans `filter` disables from the layer, and with them the
+    /// `client_id` and trace context of the events emitted in them.
+    pub fn with_event_filter<S, F>(self, filter: F) -> Filtered<Self, EventFilter<F>, S>
+    where
+        S: tracing::Subscriber + for<'lookup> LookupSpan<'lookup>,
+        F: Filter<S>,
+    {
+        self.with_filter(EventFilter(filter))
+    }
+}
+
+/// Per-layer filter applying `F` to events only, every span is enabled.
+///
+/// A span below the level of the `grpc` output still routes the events in it
+/// to its `client_id` and gives them its trace, so `GrpcLayer` has to see it.
+pub struct EventFilter<F>(pub F);
+
+impl<S, F: Filter<S>> Filter<S> for EventFilter<F> {
+    fn enabled(&self, meta: &Metadata<'_>, cx: &Context<'_, S>) -> bool {
+        meta.is_span() || self.0.enabled(meta, cx)
+    }
+
+    fn callsite_enabled(&self, meta: &'static Metadata<'static>) -> Interest {
+        if meta.is_span() {
+            Interest::always()
+        } else {
+            self.0.callsite_enabled(meta)
+        }
+    }
+
+    fn event_enabled(&self, event: &tracing::Event<'_>, cx: &Context<'_, S>) -> bool {
+        self.0.event_enabled(event, cx)
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        // Spans of every level
+        Some(LevelFilter::TRACE)
+    }
+
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        self.0.on_new_span(attrs, id, ctx)
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        self.0.on_record(id, values, ctx)
+    }
+
+    fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
+        self.0.on_enter(id, ctx)
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: Context<'_, S>) {
+        self.0.on_exit(id, ctx)
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        self.0.on_close(id, ctx)
+    }
+}
+
+impl SpanFields {
+    /// The `client_id` recorded on the span, addressing its events to one client
+    fn client_id(&self) -> Option<String> {
+        self.0
+            .iter()
+            .find(|(name, _)| name == "client_id")
+            .and_then(|(_, value)| match &value.value {
+                Some(Value::StringValue(id)) => Some(id.clone()),
+                Some(_) => Some(value.to_string()),
+                None => None,
+            })
+    }
+}
+
 impl<S> Layer<S> for GrpcLayer
 where
-    S: tracing::Subscriber,
+    S: tracing::Subscriber + for<'lookup> LookupSpan<'lookup>,
 {
+    fn on_new_span(
+        &self,
+        attrs: &span::Attributes<'_>,
+        id: &span::Id,
+        ctx: Context<'_, S>,
+    ) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut recorder = FieldRecorder::default();
+        attrs.record(&mut recorder);
+
+        // An explicit traceparent continues a remote trace, otherwise
+        // inherit the trace of the parent span
+        let remote = recorder.fields.iter().find_map(|(name, value)| match &value.value {
+            Some(Value::StringValue(traceparent)) if name == TRACEPARENT => {
+                TraceContext::from_traceparent(traceparent)
+            }
+            _ => None,
+        });
+        let trace = remote.unwrap_or_else(|| {
+            span.parent()
+                .and_then(|parent| parent.extensions().get::<TraceContext>().map(TraceContext::child))
+                .unwrap_or_else(TraceContext::root)
+        });
+
+        let mut extensions = span.extensions_mut();
+        SpanFields::record(&mut extensions, recorder);
+        extensions.insert(trace);
+    }
+
+    fn on_record(
+        &self,
+        id: &span::Id,
+        values: &span::Record<'_>,
+        ctx: Context<'_, S>,
+    ) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut recorder = FieldRecorder::default();
+        values.record(&mut recorder);
+
+        SpanFields::record(&mut span.extensions_mut(), recorder);
+    }
+
     fn on_event(
         &self,
         event: &tracing::Event<'_>,
-        _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ctx: Context<'_, S>,
     ) {
         // Define prefixes to filter in a single array
         const INTERNAL_PREFIXES: &[&str] = &[
@@ -29,6 +172,9 @@ where
             // "http::",
         ];
 
+        // Transport events of the push stream itself, forwarding them would loop
+        const FORWARD_EXCLUDED_PREFIXES: &[&str] = &["h2::", "tonic::", "hyper::", "tower::"];
+
         // Check if target starts with any internal prefix
         let target = event.metadata().target();
         if INTERNAL_PREFIXES
@@ -37,30 +183,85 @@ where
         {
             return;
         }
+        if matches!(self.sink, GrpcSink::Forward(_))
+            && FORWARD_EXCLUDED_PREFIXES
+                .iter()
+                .any(|prefix| target.starts_with(prefix))
+        {
+            return;
+        }
 
         struct LogVisitor {
-            message: String,
             server_id: String,
             client_id: Option<String>,
+            fields: FieldRecorder,
+        }
+
+        impl LogVisitor {
+            // Fields that route the message rather than describe it
+            fn is_routing(field: &Field) -> bool {
+                matches!(field.name(), "server_id" | "client_id")
+            }
+
+            fn record_routing(&mut self, field: &Field, value: String) {
+                if field.name() == "server_id" {
+                    self.server_id = value;
+                } else {
+                    self.client_id = Some(value);
+                }
+            }
         }
 
         impl Visit for LogVisitor {
+            fn record_i64(&mut self, field: &Field, value: i64) {
+                if Self::is_routing(field) {
+                    self.record_routing(field, value.to_string());
+                } else {
+                    self.fields.record_i64(field, value);
+                }
+            }
+
+            fn record_u64(&mut self, field: &Field, value: u64) {
+                if Self::is_routing(field) {
+                    self.record_routing(field, value.to_string());
+                } else {
+                    self.fields.record_u64(field, value);
+                }
+            }
+
+            fn record_f64(&mut self, field: &Field, value: f64) {
+                self.fields.record_f64(field, value);
+            }
+
+            fn record_bool(&mut self, field: &Field, value: bool) {
+                self.fields.record_bool(field, value);
+            }
+
+            fn record_str(&mut self, field: &Field, value: &str) {
+                if Self::is_routing(field) {
+                    self.record_routing(field, value.to_string());
+                } else {
+                    self.fields.record_str(field, value);
+                }
+            }
+
             fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
-                match field.name() {
-                    "message" => self.message = format!("{:?}", value),
-                    "server_id" => self.server_id = format!("{:?}", value),
-                    "client_id" => self.client_id = Some(format!("{:?}", value)),
-                    _ => {}
+                if Self::is_routing(field) {
+                    // `client_id = %id` lands here, already formatted with Display
+                    self.record_routing(field, format!("{:?}", value));
+                } else {
+                    self.fields.record_debug(field, value);
                 }
             }
         }
 
         let mut visitor = LogVisitor {
-            message: String::new(),
             server_id: String::new(),
             client_id: None,
+            fields: FieldRecorder::default(),
         };
         event.record(&mut visitor);
+        let message = visitor.fields.message.take().unwrap_or_default();
 
         // Define message patterns to filter
         const FILTERED_PATTERNS: &[&str] = &[
@@ -84,29 +285,28 @@ where
         ];
 
         // Skip if message is empty or contains any filtered pattern
-        if visitor.message.trim().is_empty()
+        if message.trim().is_empty()
             || FILTERED_PATTERNS
                 .iter()
-                .any(|pattern| visitor.message.contains(pattern))
+                .any(|pattern| message.contains(pattern))
         {
             return;
         }
 
-        let log = LogMessage {
+        let now = chrono::Local::now();
+        let mut log = LogMessage {
             timestamp: if self.config.include_timestamp {
-                Some(chrono::Local::now().to_rfc3339())
+                Some(to_timestamp(now.to_utc()))
             } else {
                 None
             },
+            utc_offset_secs: now.offset().local_minus_utc(),
             target_client_id: None,
-            level: Some(event.metadata().level().to_string()),
-            message: visitor.message,
+            level: Level::from(*event.metadata().level()) as i32,
+            message,
             server_id: self.server_id.clone(),
-            target: if self.config.include_target {
-                Some(target.to_string())
-            } else {
-                None
-            },
+            // Always sent, whatever include_target: subscription filters match on it
+            target: Some(target.to_string()),
             thread_id: if self.config.include_thread_id {
                 Some(format!("{:?}", std::thread::current().id()))
             } else {
@@ -118,29 +318,47 @@ where
                 None
             },
             line: if self.config.include_line {
-                Some(event.metadata().line().unwrap_or(0).to_string())
+                event.metadata().line()
             } else {
                 None
             },
+            fields: visitor.fields.fields.into_iter().collect(),
+            ..Default::default()
         };
 
-        // Create a cloned service for the spawn
-        let service = self.service.clone();
-
-        // If there's a client_id in the span, create a filtered broadcast
-        if let Some(client_id) = visitor.client_id {
-            let log_clone = log.clone();
-            let client_id_clone = client_id.clone();
-            tokio::spawn(async move {
-                service
-                    .broadcast_log_filtered(log_clone, client_id_clone)
-                    .await;
-            });
-        } else {
-            let log_clone = log.clone();
-            tokio::spawn(async move {
-                service.broadcast_log(log_clone).await;
-            });
+        let mut client_id = visitor.client_id;
+        let mut span_client_id = None;
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                if let Some(trace) = extensions.get::<TraceContext>() {
+                    // Spans are visited outermost first, the innermost wins
+                    log.trace_id = Some(trace.trace_id.clone());
+                    log.span_id = Some(trace.span_id.clone());
+                    log.parent_span_id = trace.parent_span_id.clone();
+                }
+                if let Some(id) = extensions.get::<SpanFields>().and_then(SpanFields::client_id) {
+                    span_client_id = Some(id);
+                }
+                log.spans.push(SpanContext {
+                    name: span.name().to_string(),
+                    fields: extensions
+                        .get::<SpanFields>()
+                        .map(|fields| fields.0.iter().cloned().collect())
+                        .unwrap_or_default(),
+                });
+            }
+        }
+        // A client_id on the event wins over one set on an enclosing span
+        if client_id.is_none() {
+            client_id = span_client_id;
+        }
+
+        // The remote logger takes care of targeting forwarded messages
+        log.target_client_id = client_id;
+        match &self.sink {
+            GrpcSink::Local(service) => service.dispatch(log),
+            GrpcSink::Forward(forwarder) => forwarder.send(log),
         }
     }
 }
@@ -1,12 +1,166 @@
 use serde::Deserialize;
+use std::fs;
+use tonic::codec::CompressionEncoding;
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint, Identity, ServerTlsConfig};
 
+mod forwarder;
 mod layer;
+mod trace;
 mod writer;
 
-pub use layer::GrpcLayer;
+pub use forwarder::LogForwarder;
+pub use layer::{EventFilter, GrpcLayer, GrpcSink};
+pub use trace::TRACEPARENT;
 
 #[derive(Debug, Deserialize)]
 pub struct GrpcConfig {
     pub address: String,
     pub port: u16,
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    pub auth: Option<AuthConfig>,
+    /// Serve, or connect, over TLS
+    pub tls: Option<TlsConfig>,
+    /// Client side: messages a `LogForwarder` buffers while the logger is
+    /// unreachable, newer ones are dropped
+    #[serde(default = "default_forward_buffer")]
+    pub forward_buffer: usize,
+}
+
+impl GrpcConfig {
+    /// Endpoint of the logger this section points to, over TLS when `tls` is set
+    pub fn endpoint(&self) -> Result<Endpoint, Box<dyn std::error::Error + Send + Sync>> {
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
+        let mut endpoint =
+            Endpoint::from_shared(format!("{}://{}:{}", scheme, self.address, self.port))?;
+        if let Some(tls) = &self.tls {
+            endpoint = endpoint.tls_config(tls.client_config(&self.address)?)?;
+        }
+        Ok(endpoint)
+    }
+}
+
+/// PEM files used for TLS. The same section describes the server, and how
+/// clients reach it.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TlsConfig {
+    /// Certificate chain presented by this process: the server certificate,
+    /// or the client certificate when connecting to a logger requiring one
+    pub cert: Option<String>,
+    /// Private key of `cert`
+    pub key: Option<String>,
+    /// Server side: require client certificates signed by this CA (mutual TLS)
+    pub client_ca: Option<String>,
+    /// Client side: CA the server certificate must be signed by. The system
+    /// roots are used when unset.
+    pub server_ca: Option<String>,
+    /// Client side: name expected in the server certificate, defaults to `address`
+    pub domain: Option<String>,
+}
+
+impl TlsConfig {
+    pub fn server_config(&self) -> Result<ServerTlsConfig, Box<dyn std::error::Error + Send + Sync>> {
+        let identity = self
+            .identity()?
+            .ok_or("TLS requires both cert and key on the server")?;
+        let mut config = ServerTlsConfig::new().identity(identity);
+        if let Some(client_ca) = &self.client_ca {
+            config = config.client_ca_root(Certificate::from_pem(read_pem(client_ca)?));
+        }
+        Ok(config)
+    }
+
+    pub fn client_config(
+        &self,
+        address: &str,
+    ) -> Result<ClientTlsConfig, Box<dyn std::error::Error + Send + Sync>> {
+        let mut config =
+            ClientTlsConfig::new().domain_name(self.domain.as_deref().unwrap_or(address));
+        config = match &self.server_ca {
+            Some(server_ca) => config.ca_certificate(Certificate::from_pem(read_pem(server_ca)?)),
+            None => config.with_native_roots(),
+        };
+        if let Some(identity) = self.identity()? {
+            config = config.identity(identity);
+        }
+        Ok(config)
+    }
+
+    fn identity(&self) -> Result<Option<Identity>, Box<dyn std::error::Error + Send + Sync>> {
+        match (&self.cert, &self.key) {
+            (Some(cert), Some(key)) => Ok(Some(Identity::from_pem(read_pem(cert)?, read_pem(key)?))),
+            (None, None) => Ok(None),
+            _ => Err("TLS cert and key must be set together".into()),
+        }
+    }
+}
+
+fn read_pem(path: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e).into())
+}
+
+/// Bearer tokens protecting the log and admin services
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuthConfig {
+    /// Tokens the log service accepts. Authentication is off when empty.
+    #[serde(default)]
+    pub tokens: Vec<AuthToken>,
+    /// Tokens `AdminService` accepts, also valid for the log service.
+    /// `AdminService` refuses every call when empty.
+    #[serde(default)]
+    pub admin_tokens: Vec<AuthToken>,
+    /// Token this process sends when forwarding its logs to a remote logger
+    pub client_token: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuthToken {
+    pub token: String,
+    /// Name recorded for clients using this token, reported by ListClients
+    pub identity: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl From<Compression> for CompressionEncoding {
+    fn from(compression: Compression) -> Self {
+        match compression {
+            Compression::Gzip => CompressionEncoding::Gzip,
+            Compression::Zstd => CompressionEncoding::Zstd,
+        }
+    }
+}
+
+/// Encodings used on log streams, for subscriptions and ingest alike
+#[derive(Debug, Clone, Deserialize)]
+pub struct CompressionConfig {
+    /// Encodings accepted from the peer
+    #[serde(default = "default_accept")]
+    pub accept: Vec<Compression>,
+    /// Encodings used for what we send, in order of preference. The server only
+    /// uses one the client accepts, a forwarding client uses the first one.
+    #[serde(default)]
+    pub send: Vec<Compression>,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            accept: default_accept(),
+            send: Vec::new(),
+        }
+    }
+}
+
+fn default_forward_buffer() -> usize {
+    10000
+}
+
+fn default_accept() -> Vec<Compression> {
+    vec![Compression::Gzip, Compression::Zstd]
 }
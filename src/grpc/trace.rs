@@ -0,0 +1,71 @@
+use uuid::Uuid;
+
+/// Name of the span field (and gRPC metadata key) carrying a W3C `traceparent`
+pub const TRACEPARENT: &str = "traceparent";
+
+/// W3C trace context of a span, stored in the span's extensions.
+///
+/// Ids are lowercase hex: 32 characters for the trace id, 16 for span ids.
+#[derive(Debug, Clone)]
+pub struct TraceContext {
+    pub trace_id: String,
+    pub span_id: String,
+    pub parent_span_id: Option<String>,
+}
+
+impl TraceContext {
+    /// Context for a span with no parent: starts a new trace
+    pub fn root() -> Self {
+        Self {
+            trace_id: format!("{:032x}", Uuid::new_v4().as_u128()),
+            span_id: new_span_id(),
+            parent_span_id: None,
+        }
+    }
+
+    /// Context for a span nested in `self`
+    pub fn child(&self) -> Self {
+        Self {
+            trace_id: self.trace_id.clone(),
+            span_id: new_span_id(),
+            parent_span_id: Some(self.span_id.clone()),
+        }
+    }
+
+    /// Context for a span continuing the remote trace described by a
+    /// `traceparent` header (`00-<trace-id>-<parent-id>-<flags>`)
+    pub fn from_traceparent(traceparent: &str) -> Option<Self> {
+        let mut parts = traceparent.trim().split('-');
+        let version = parts.next()?;
+        let trace_id = parts.next()?;
+        let parent_id = parts.next()?;
+        let flags = parts.next()?;
+
+        let valid = is_hex(version, 2)
+            && version != "ff"
+            // Version 00 has exactly four parts, later versions may add more
+            && (version != "00" || parts.next().is_none())
+            && is_hex(trace_id, 32)
+            && is_hex(parent_id, 16)
+            && is_hex(flags, 2)
+            && trace_id.bytes().any(|b| b != b'0')
+            && parent_id.bytes().any(|b| b != b'0');
+        if !valid {
+            return None;
+        }
+
+        Some(Self {
+            trace_id: trace_id.to_ascii_lowercase(),
+            span_id: new_span_id(),
+            parent_span_id: Some(parent_id.to_ascii_lowercase()),
+        })
+    }
+}
+
+fn new_span_id() -> String {
+    format!("{:016x}", Uuid::new_v4().as_u128() as u64)
+}
+
+fn is_hex(value: &str, len: usize) -> bool {
+    value.len() == len && value.bytes().all(|b| b.is_ascii_hexdigit())
+}
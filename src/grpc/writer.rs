@@ -2,6 +2,7 @@ use std::io::Write;
 use tokio::sync::mpsc;
 use tracing_subscriber::fmt::MakeWriter;
 
+#[allow(dead_code)]
 #[derive(Clone)]
 pub struct GrpcWriter {
     sender: mpsc::UnboundedSender<String>,
@@ -20,7 +21,7 @@ impl Write for GrpcWriter {
         if let Ok(log_str) = String::from_utf8(buf.to_vec()) {
             self.sender
                 .send(log_str)
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+                .map_err(|e| std::io::Error::other(e.to_string()))?;
         }
         Ok(buf.len())
     }
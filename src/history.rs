@@ -0,0 +1,343 @@
+use crate::server_build::logging::v2::{LogMessage, SequenceGap};
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, VecDeque};
+use uuid::Uuid;
+
+/// A retained message together with the time the server received it.
+///
+/// The receive time is used for "since" lookups because `LogMessage.timestamp`
+/// is only filled in when the producer has `include_timestamp` enabled.
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    /// Position in the stream of retained messages, never reused
+    pub id: u64,
+    pub received_at: DateTime<Utc>,
+    pub log: LogMessage,
+}
+
+/// Fixed-size ring buffer of the most recent messages seen by the server.
+///
+/// Also hands out the per-origin sequence numbers, so that resumed
+/// subscriptions can tell which messages they missed. Sequences belong to
+/// the history's epoch, which is new every time one is created.
+#[derive(Debug)]
+pub struct LogHistory {
+    capacity: usize,
+    next_id: u64,
+    entries: VecDeque<HistoryEntry>,
+    sequences: HashMap<String, u64>,
+    epoch: String,
+}
+
+/// Criteria for searching retained messages
+#[derive(Debug, Default)]
+pub struct HistoryQuery {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// Only entries older than this id, used for pagination
+    pub before_id: Option<u64>,
+    pub limit: usize,
+}
+
+impl LogHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            next_id: 0,
+            entries: VecDeque::with_capacity(capacity),
+            sequences: HashMap::new(),
+            epoch: Uuid::new_v4().simple().to_string(),
+        }
+    }
+
+    /// Epoch of the sequences handed out by this history
+    pub fn epoch(&self) -> &str {
+        &self.epoch
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Assign the next sequence number of the message's origin
+    pub fn stamp(&mut self, log: &mut LogMessage) {
+        let sequence = self.sequences.entry(origin(log).to_string()).or_default();
+        *sequence += 1;
+        log.sequence = *sequence;
+        log.epoch = self.epoch.clone();
+    }
+
+    pub fn push(&mut self, log: LogMessage) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(HistoryEntry {
+            id: self.next_id,
+            received_at: Utc::now(),
+            log,
+        });
+        self.next_id += 1;
+    }
+
+    /// Messages to replay to a new subscriber, oldest first.
+    ///
+    /// Only entries accepted by `predicate` are considered. `since` keeps entries
+    /// received at or after that time and `last` keeps the newest `last` of them.
+    pub fn replay(
+        &self,
+        last: Option<usize>,
+        since: Option<DateTime<Utc>>,
+        predicate: impl Fn(&LogMessage) -> bool,
+    ) -> Vec<LogMessage> {
+        let limit = last.unwrap_or(usize::MAX);
+        let mut replay: Vec<LogMessage> = self
+            .entries
+            .iter()
+            .rev()
+            .take_while(|entry| since.is_none_or(|since| entry.received_at >= since))
+            .filter(|entry| predicate(&entry.log))
+            .take(limit)
+            .map(|entry| entry.log.clone())
+            .collect();
+        replay.reverse();
+        replay
+    }
+
+    /// Search retained messages, newest first.
+    ///
+    /// Returns the matching messages and, when more results remain, the id to
+    /// pass as `before_id` to fetch the next page.
+    pub fn query(
+        &self,
+        query: &HistoryQuery,
+        predicate: impl Fn(&LogMessage) -> bool,
+    ) -> (Vec<LogMessage>, Option<u64>) {
+        let mut matches = self
+            .entries
+            .iter()
+            .rev()
+            .filter(|entry| query.before_id.is_none_or(|before| entry.id < before))
+            .filter(|entry| query.until.is_none_or(|until| entry.received_at < until))
+            .take_while(|entry| query.since.is_none_or(|since| entry.received_at >= since))
+            .filter(|entry| predicate(&entry.log));
+
+        let logs: Vec<&HistoryEntry> = matches.by_ref().take(query.limit).collect();
+        let next = match (logs.last(), matches.next()) {
+            (Some(last), Some(_)) => Some(last.id),
+            _ => None,
+        };
+        (logs.into_iter().map(|entry| entry.log.clone()).collect(), next)
+    }
+
+    /// Latest sequence assigned to each origin
+    pub fn sequences(&self) -> &HashMap<String, u64> {
+        &self.sequences
+    }
+
+    /// Messages a subscriber missed after `cursor` (last sequence seen per
+    /// origin), oldest first, and the ranges that are no longer retained.
+    /// Origins missing from the cursor appeared after it was taken and were
+    /// entirely missed.
+    ///
+    /// `epoch` is the one the cursor was taken in, empty for the current one.
+    /// A cursor from another epoch predates a restart: each of its origins
+    /// gets a restart gap and is replayed from its first retained message.
+    pub fn resume(
+        &self,
+        cursor: &HashMap<String, u64>,
+        epoch: &str,
+        predicate: impl Fn(&LogMessage) -> bool,
+    ) -> (Vec<LogMessage>, Vec<SequenceGap>) {
+        let restarted = !epoch.is_empty() && epoch != self.epoch;
+        let mut gaps = Vec::new();
+        let mut cursor = cursor.clone();
+        if restarted {
+            for (server_id, last_seen) in cursor.iter_mut() {
+                gaps.push(SequenceGap {
+                    server_id: server_id.clone(),
+                    first_missing: *last_seen + 1,
+                    last_missing: 0,
+                    restarted: true,
+                });
+                *last_seen = 0;
+            }
+        }
+        for server_id in self.sequences.keys() {
+            cursor.entry(server_id.clone()).or_insert(0);
+        }
+        for (server_id, last_seen) in &cursor {
+            let last_seen = *last_seen;
+            let latest = self.sequences.get(server_id).copied().unwrap_or(0);
+            if latest <= last_seen {
+                continue;
+            }
+            // Retained entries of an origin are contiguous, eviction is FIFO
+            let first_retained = self
+                .entries
+                .iter()
+                .find(|entry| origin(&entry.log) == server_id)
+                .map_or(latest + 1, |entry| entry.log.sequence);
+            if first_retained > last_seen + 1 {
+                gaps.push(SequenceGap {
+                    server_id: server_id.clone(),
+                    first_missing: last_seen + 1,
+                    last_missing: first_retained - 1,
+                    restarted: false,
+                });
+            }
+        }
+
+        let logs = self
+            .entries
+            .iter()
+            .filter(|entry| {
+                cursor
+                    .get(origin(&entry.log))
+                    .is_some_and(|&last_seen| entry.log.sequence > last_seen)
+            })
+            .filter(|entry| predicate(&entry.log))
+            .map(|entry| entry.log.clone())
+            .collect();
+        (logs, gaps)
+    }
+}
+
+/// Key of the sequence a message belongs to
+fn origin(log: &LogMessage) -> &str {
+    log.server_id.as_deref().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// History of `capacity` holding `count` messages from each origin
+    fn history(capacity: usize, origins: &[&str], count: usize) -> LogHistory {
+        let mut history = LogHistory::new(capacity);
+        for i in 1..=count {
+            for server_id in origins {
+                let mut log = LogMessage {
+                    server_id: Some(server_id.to_string()),
+                    message: format!("{}{}", server_id, i),
+                    ..Default::default()
+                };
+                history.stamp(&mut log);
+                history.push(log);
+            }
+        }
+        history
+    }
+
+    fn cursor(entries: &[(&str, u64)]) -> HashMap<String, u64> {
+        entries
+            .iter()
+            .map(|(server_id, sequence)| (server_id.to_string(), *sequence))
+            .collect()
+    }
+
+    fn messages(logs: &[LogMessage]) -> Vec<&str> {
+        logs.iter().map(|log| log.message.as_str()).collect()
+    }
+
+    #[test]
+    fn resumes_after_the_cursor() {
+        let history = history(10, &["a", "b"], 3);
+        let (logs, gaps) = history.resume(&cursor(&[("a", 1), ("b", 3)]), "", |_| true);
+        assert_eq!(messages(&logs), ["a2", "a3"]);
+        assert!(gaps.is_empty());
+    }
+
+    #[test]
+    fn replays_origins_missing_from_the_cursor() {
+        let history = history(10, &["a", "b"], 2);
+        let (logs, gaps) = history.resume(&cursor(&[("a", 1)]), history.epoch(), |_| true);
+        assert_eq!(messages(&logs), ["b1", "a2", "b2"]);
+        assert!(gaps.is_empty());
+    }
+
+    #[test]
+    fn reports_evicted_messages_of_origins_missing_from_the_cursor() {
+        let history = history(4, &["a", "b"], 3);
+        let (logs, gaps) = history.resume(&cursor(&[("a", 2)]), "", |_| true);
+        assert_eq!(messages(&logs), ["b2", "a3", "b3"]);
+        assert_eq!(
+            gaps,
+            [SequenceGap {
+                server_id: "b".to_string(),
+                first_missing: 1,
+                last_missing: 1,
+                restarted: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_evicted_messages_as_a_gap() {
+        let history = history(3, &["a"], 5);
+        let (logs, gaps) = history.resume(&cursor(&[("a", 1)]), "", |_| true);
+        assert_eq!(messages(&logs), ["a3", "a4", "a5"]);
+        assert_eq!(
+            gaps,
+            [SequenceGap {
+                server_id: "a".to_string(),
+                first_missing: 2,
+                last_missing: 2,
+                restarted: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_a_gap_when_nothing_is_retained() {
+        let history = history(0, &["a"], 4);
+        let (logs, gaps) = history.resume(&cursor(&[("a", 1)]), "", |_| true);
+        assert!(logs.is_empty());
+        assert_eq!(
+            gaps,
+            [SequenceGap {
+                server_id: "a".to_string(),
+                first_missing: 2,
+                last_missing: 4,
+                restarted: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn accepts_the_current_epoch() {
+        let history = history(10, &["a"], 3);
+        let (logs, gaps) = history.resume(&cursor(&[("a", 2)]), history.epoch(), |_| true);
+        assert_eq!(messages(&logs), ["a3"]);
+        assert!(gaps.is_empty());
+    }
+
+    #[test]
+    fn reports_a_restart_on_another_epoch() {
+        let history = history(10, &["a"], 2);
+        let (logs, gaps) = history.resume(&cursor(&[("a", 7)]), "previous", |_| true);
+        assert_eq!(messages(&logs), ["a1", "a2"]);
+        assert_eq!(
+            gaps,
+            [SequenceGap {
+                server_id: "a".to_string(),
+                first_missing: 8,
+                last_missing: 0,
+                restarted: true,
+            }]
+        );
+    }
+
+    #[test]
+    fn filters_resumed_messages() {
+        let history = history(10, &["a"], 4);
+        let (logs, gaps) = history.resume(&cursor(&[("a", 1)]), "", |log| log.message != "a3");
+        assert_eq!(messages(&logs), ["a2", "a4"]);
+        assert!(gaps.is_empty());
+    }
+}
@@ -0,0 +1,253 @@
+//! Serves the original `logging` package on top of `logging.v2`, so existing
+//! clients keep working. Requests are converted to v2 and handled by
+//! `LoggingService`, and messages are converted back on the way out.
+
+use crate::convert::{to_origin_time, to_timestamp};
+use crate::server_build::logging as v1;
+use crate::server_build::logging::v2;
+use crate::server_build::LoggingService;
+use chrono::DateTime;
+use futures::{Stream, StreamExt};
+use std::pin::Pin;
+use std::str::FromStr;
+use tonic::{Request, Response, Status, Streaming};
+use tracing::info;
+
+#[derive(Debug, Clone)]
+pub(crate) struct LegacyLogService {
+    service: LoggingService,
+}
+
+impl LegacyLogService {
+    pub(crate) fn new(service: LoggingService) -> Self {
+        Self { service }
+    }
+}
+
+#[tonic::async_trait]
+impl v1::log_service_server::LogService for LegacyLogService {
+    type SubscribeToLogsStream =
+        Pin<Box<dyn Stream<Item = Result<v1::LogMessage, Status>> + Send>>;
+
+    async fn subscribe_to_logs(
+        &self,
+        request: Request<v1::SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeToLogsStream>, Status> {
+        // Keep metadata and extensions, they carry the peer address
+        let (metadata, extensions, request) = request.into_parts();
+        let request = v2::SubscribeRequest {
+            client_id: request.client_id,
+            client_type: request.client_type,
+            server_name: request.server_name,
+            filter: request.filter.map(filter_from_v1).transpose()?,
+            history_last: request.history_last,
+            history_since: request
+                .history_since
+                .as_deref()
+                .map(|since| parse_timestamp("history_since", since))
+                .transpose()?,
+            // v2-only features
+            ..Default::default()
+        };
+
+        let stream = v2::log_service_server::LogService::subscribe_to_logs(
+            &self.service,
+            Request::from_parts(metadata, extensions, request),
+        )
+        .await?
+        .into_inner();
+        Ok(Response::new(Box::pin(
+            stream.map(|result| result.map(message_to_v1)),
+        )))
+    }
+
+    async fn push_logs(
+        &self,
+        request: Request<Streaming<v1::LogMessage>>,
+    ) -> Result<Response<v1::PushLogsResponse>, Status> {
+        let mut stream = request.into_inner();
+        let mut accepted = 0;
+        while let Some(log) = stream.message().await? {
+            self.service.broadcast_log(message_from_v1(log)).await;
+            accepted += 1;
+        }
+
+        info!("Legacy log producer disconnected after {} messages", accepted);
+        Ok(Response::new(v1::PushLogsResponse { accepted }))
+    }
+
+    async fn query_logs(
+        &self,
+        request: Request<v1::QueryLogsRequest>,
+    ) -> Result<Response<v1::QueryLogsResponse>, Status> {
+        let (metadata, extensions, request) = request.into_parts();
+        let request = v2::QueryLogsRequest {
+            filter: request.filter.map(filter_from_v1).transpose()?,
+            since: request
+                .since
+                .as_deref()
+                .map(|since| parse_timestamp("since", since))
+                .transpose()?,
+            until: request
+                .until
+                .as_deref()
+                .map(|until| parse_timestamp("until", until))
+                .transpose()?,
+            text: request.text,
+            page_size: request.page_size,
+            page_token: request.page_token,
+        };
+
+        let response = v2::log_service_server::LogService::query_logs(
+            &self.service,
+            Request::from_parts(metadata, extensions, request),
+        )
+        .await?
+        .into_inner();
+        Ok(Response::new(v1::QueryLogsResponse {
+            logs: response.logs.into_iter().map(message_to_v1).collect(),
+            next_page_token: response.next_page_token,
+        }))
+    }
+}
+
+fn parse_timestamp(field: &str, value: &str) -> Result<prost_types::Timestamp, Status> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| to_timestamp(time.to_utc()))
+        .map_err(|e| Status::invalid_argument(format!("Invalid {}: {}", field, e)))
+}
+
+fn filter_from_v1(filter: v1::LogFilter) -> Result<v2::LogFilter, Status> {
+    let min_level = match filter.min_level.as_deref().map(str::trim) {
+        None | Some("") => v2::Level::Unspecified,
+        Some(level) => tracing::Level::from_str(level)
+            .map_err(|_| Status::invalid_argument(format!("Invalid min_level: {}", level)))?
+            .into(),
+    };
+
+    Ok(v2::LogFilter {
+        min_level: min_level as i32,
+        include_targets: filter.include_targets,
+        exclude_targets: filter.exclude_targets,
+        server_ids: filter.server_ids,
+    })
+}
+
+fn message_to_v1(log: v2::LogMessage) -> v1::LogMessage {
+    v1::LogMessage {
+        // v1 clients expect the emitter's local time, as chrono::Local used to produce
+        timestamp: log
+            .timestamp
+            .as_ref()
+            .and_then(|timestamp| to_origin_time(timestamp, log.utc_offset_secs))
+            .map(|time| time.to_rfc3339()),
+        level: log
+            .level()
+            .to_tracing()
+            .map(|level| level.to_string()),
+        message: log.message,
+        target: log.target,
+        thread_id: log.thread_id,
+        file: log.file,
+        line: log.line.map(|line| line.to_string()),
+        server_id: log.server_id,
+        target_client_id: log.target_client_id,
+        fields: log
+            .fields
+            .into_iter()
+            .map(|(name, value)| (name, field_to_v1(value)))
+            .collect(),
+        spans: log
+            .spans
+            .into_iter()
+            .map(|span| v1::SpanContext {
+                name: span.name,
+                fields: span
+                    .fields
+                    .into_iter()
+                    .map(|(name, value)| (name, field_to_v1(value)))
+                    .collect(),
+            })
+            .collect(),
+        trace_id: log.trace_id,
+        span_id: log.span_id,
+        parent_span_id: log.parent_span_id,
+    }
+}
+
+fn message_from_v1(log: v1::LogMessage) -> v2::LogMessage {
+    let time = log
+        .timestamp
+        .as_deref()
+        .and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok());
+
+    v2::LogMessage {
+        timestamp: time.map(|time| to_timestamp(time.to_utc())),
+        utc_offset_secs: time.map_or(0, |time| time.offset().local_minus_utc()),
+        level: log
+            .level
+            .as_deref()
+            .and_then(|level| tracing::Level::from_str(level).ok())
+            .map_or(v2::Level::Unspecified, v2::Level::from) as i32,
+        message: log.message,
+        target: log.target,
+        thread_id: log.thread_id,
+        file: log.file,
+        line: log.line.and_then(|line| line.parse().ok()),
+        server_id: log.server_id,
+        target_client_id: log.target_client_id,
+        fields: log
+            .fields
+            .into_iter()
+            .map(|(name, value)| (name, field_from_v1(value)))
+            .collect(),
+        spans: log
+            .spans
+            .into_iter()
+            .map(|span| v2::SpanContext {
+                name: span.name,
+                fields: span
+                    .fields
+                    .into_iter()
+                    .map(|(name, value)| (name, field_from_v1(value)))
+                    .collect(),
+            })
+            .collect(),
+        trace_id: log.trace_id,
+        span_id: log.span_id,
+        parent_span_id: log.parent_span_id,
+        // Sequence is assigned on ingest
+        ..Default::default()
+    }
+}
+
+fn field_to_v1(value: v2::FieldValue) -> v1::FieldValue {
+    use v1::field_value::Value as V1;
+    use v2::field_value::Value as V2;
+
+    v1::FieldValue {
+        value: value.value.map(|value| match value {
+            V2::StringValue(v) => V1::StringValue(v),
+            V2::I64Value(v) => V1::I64Value(v),
+            V2::U64Value(v) => V1::U64Value(v),
+            V2::F64Value(v) => V1::F64Value(v),
+            V2::BoolValue(v) => V1::BoolValue(v),
+        }),
+    }
+}
+
+fn field_from_v1(value: v1::FieldValue) -> v2::FieldValue {
+    use v1::field_value::Value as V1;
+    use v2::field_value::Value as V2;
+
+    v2::FieldValue {
+        value: value.value.map(|value| match value {
+            V1::StringValue(v) => V2::StringValue(v),
+            V1::I64Value(v) => V2::I64Value(v),
+            V1::U64Value(v) => V2::U64Value(v),
+            V1::F64Value(v) => V2::F64Value(v),
+            V1::BoolValue(v) => V2::BoolValue(v),
+        }),
+    }
+}
+
@@ -1,20 +1,48 @@
+#![allow(clippy::result_large_err)] // tonic::Status is large by design
+
+mod admin;
+mod auth;
 pub mod config;
+pub mod convert;
+pub mod fields;
+pub mod filter;
 pub mod grpc;
+pub mod history;
+mod legacy;
+mod queue;
+mod rolling;
+mod runtime;
 pub mod server_build;
 pub mod setup_logging;
 
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+/// A client subscribed to the logging service, as reported by `AdminService`
 #[derive(Debug, Clone)]
 pub struct ConnectionInfo {
     pub client_id: String,
-    pub client_type: i32,
+    pub client_type: ClientType,
     pub server_name: String,
+    pub peer_address: Option<SocketAddr>,
+    /// Identity of the token the client authenticated with
+    pub identity: Option<String>,
+    pub connected_at: DateTime<Utc>,
+    pub messages_sent: u64,
+    pub queue_depth: u64,
+    /// Paused through the `Watch` RPC
+    pub paused: bool,
+    /// Last sequence per origin acknowledged through the `Watch` RPC
+    pub acked_sequences: HashMap<String, u64>,
 }
 
-pub use crate::server_build::logging::ClientType;
+pub use crate::server_build::logging::v2::{ClientType, Level, LogMessage};
 
 pub use crate::server_build::LoggingService;
 pub use grpc::GrpcLayer;
 pub use config::{load_config, LogConfig};
+pub use filter::SubscriptionFilter;
 pub use setup_logging::setup_logging;
 
 /// Initialize the logging service with a given configuration file
@@ -1,8 +1,3 @@
-mod config;
-mod grpc;
-mod server_build;
-mod setup_logging;
-
 use grpc_logger::init;
 
 #[tokio::main]
@@ -0,0 +1,229 @@
+//! Bounded message queue between the broadcaster and a subscriber's stream.
+
+use crate::config::OverflowPolicy;
+use crate::convert::to_timestamp;
+use crate::server_build::logging::v2::field_value::Value;
+use crate::server_build::logging::v2::{Level, LogMessage};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PushError {
+    /// The subscriber's stream is gone
+    Closed,
+    /// The queue is full and the policy is to disconnect
+    Full,
+}
+
+#[derive(Debug)]
+pub(crate) struct SubscriberQueue {
+    state: Mutex<State>,
+    notify: Notify,
+    capacity: usize,
+    policy: OverflowPolicy,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    messages: VecDeque<LogMessage>,
+    // Dropped from the front, reported before the remaining messages
+    dropped_oldest: u64,
+    // Dropped on arrival, reported after the messages queued before them
+    dropped_newest: u64,
+    closed: bool,
+}
+
+impl SubscriberQueue {
+    pub(crate) fn new(capacity: usize, policy: OverflowPolicy) -> (Arc<Self>, QueueReceiver) {
+        let queue = Arc::new(Self {
+            state: Mutex::new(State::default()),
+            notify: Notify::new(),
+            capacity: capacity.max(1),
+            policy,
+        });
+        let receiver = QueueReceiver {
+            queue: queue.clone(),
+        };
+        (queue, receiver)
+    }
+
+    pub(crate) fn push(&self, log: LogMessage) -> Result<(), PushError> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(PushError::Closed);
+        }
+
+        if state.messages.len() >= self.capacity {
+            match self.policy {
+                OverflowPolicy::DropOldest => {
+                    state.messages.pop_front();
+                    state.dropped_oldest += 1;
+                }
+                OverflowPolicy::DropNewest => {
+                    state.dropped_newest += 1;
+                    return Ok(());
+                }
+                OverflowPolicy::Disconnect => {
+                    state.closed = true;
+                    return Err(PushError::Full);
+                }
+            }
+        } else if state.dropped_newest > 0 {
+            // Room again: report what was dropped at the position it was dropped
+            let notice = dropped_message(std::mem::take(&mut state.dropped_newest));
+            state.messages.push_back(notice);
+        }
+
+        state.messages.push_back(log);
+        drop(state);
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    /// Whether the stream is gone or the queue overflowed with the
+    /// disconnect policy
+    pub(crate) fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+
+    /// Messages waiting to be sent
+    pub(crate) fn len(&self) -> usize {
+        self.state.lock().unwrap().messages.len()
+    }
+
+    fn try_pop(&self) -> Option<LogMessage> {
+        let mut state = self.state.lock().unwrap();
+        if state.dropped_oldest > 0 {
+            return Some(dropped_message(std::mem::take(&mut state.dropped_oldest)));
+        }
+        if let Some(log) = state.messages.pop_front() {
+            return Some(log);
+        }
+        (state.dropped_newest > 0)
+            .then(|| dropped_message(std::mem::take(&mut state.dropped_newest)))
+    }
+}
+
+/// Consuming end of a `SubscriberQueue`. Dropping it closes the queue.
+#[derive(Debug)]
+pub(crate) struct QueueReceiver {
+    queue: Arc<SubscriberQueue>,
+}
+
+impl QueueReceiver {
+    pub(crate) async fn recv(&self) -> LogMessage {
+        loop {
+            if let Some(log) = self.queue.try_pop() {
+                return log;
+            }
+            self.queue.notify.notified().await;
+        }
+    }
+}
+
+impl Drop for QueueReceiver {
+    fn drop(&mut self) {
+        let mut state = self.queue.state.lock().unwrap();
+        state.closed = true;
+        state.messages.clear();
+    }
+}
+
+/// Synthetic message telling a subscriber that messages were dropped because
+/// it did not keep up
+fn dropped_message(count: u64) -> LogMessage {
+    let now = chrono::Local::now();
+    LogMessage {
+        timestamp: Some(to_timestamp(now.to_utc())),
+        utc_offset_secs: now.offset().local_minus_utc(),
+        level: Level::Warn as i32,
+        message: format!(
+            "{} messages dropped, the client is not keeping up with the stream",
+            count
+        ),
+        fields: [("dropped".to_string(), Value::U64Value(count).into())].into(),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log(message: &str) -> LogMessage {
+        LogMessage {
+            message: message.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Messages waiting in the queue, drop notices as `dropped:<count>`
+    fn drain(queue: &SubscriberQueue) -> Vec<String> {
+        std::iter::from_fn(|| queue.try_pop())
+            .map(|log| match log.fields.get("dropped").and_then(|v| v.value.clone()) {
+                Some(Value::U64Value(count)) => format!("dropped:{}", count),
+                _ => log.message,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn drop_oldest_reports_before_remaining_messages() {
+        let (queue, _receiver) = SubscriberQueue::new(2, OverflowPolicy::DropOldest);
+        for message in ["a", "b", "c", "d"] {
+            assert_eq!(queue.push(log(message)), Ok(()));
+        }
+        assert_eq!(drain(&queue), ["dropped:2", "c", "d"]);
+    }
+
+    #[test]
+    fn drop_newest_reports_where_messages_were_dropped() {
+        let (queue, _receiver) = SubscriberQueue::new(2, OverflowPolicy::DropNewest);
+        for message in ["a", "b", "c", "d"] {
+            assert_eq!(queue.push(log(message)), Ok(()));
+        }
+        assert_eq!(queue.try_pop().unwrap().message, "a");
+        queue.push(log("e")).unwrap();
+        assert_eq!(drain(&queue), ["b", "dropped:2", "e"]);
+    }
+
+    #[test]
+    fn drop_newest_reports_when_drained() {
+        let (queue, _receiver) = SubscriberQueue::new(1, OverflowPolicy::DropNewest);
+        for message in ["a", "b"] {
+            queue.push(log(message)).unwrap();
+        }
+        assert_eq!(drain(&queue), ["a", "dropped:1"]);
+    }
+
+    #[test]
+    fn disconnect_closes_the_queue_when_full() {
+        let (queue, _receiver) = SubscriberQueue::new(1, OverflowPolicy::Disconnect);
+        assert_eq!(queue.push(log("a")), Ok(()));
+        assert_eq!(queue.push(log("b")), Err(PushError::Full));
+        assert!(queue.is_closed());
+        assert_eq!(queue.push(log("c")), Err(PushError::Closed));
+    }
+
+    #[test]
+    fn dropping_the_receiver_closes_the_queue() {
+        let (queue, receiver) = SubscriberQueue::new(2, OverflowPolicy::DropOldest);
+        queue.push(log("a")).unwrap();
+        drop(receiver);
+        assert!(queue.is_closed());
+        assert_eq!(queue.len(), 0);
+        assert_eq!(queue.push(log("b")), Err(PushError::Closed));
+    }
+
+    #[tokio::test]
+    async fn recv_waits_for_a_message() {
+        let (queue, receiver) = SubscriberQueue::new(2, OverflowPolicy::DropOldest);
+        let pushed = queue.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            pushed.push(log("a")).unwrap();
+        });
+        assert_eq!(receiver.recv().await.message, "a");
+    }
+}
@@ -0,0 +1,606 @@
+//! Writer of the `file` output. The current file keeps its configured name,
+//! rotated files get the period or time of their rotation appended, are
+//! compressed in the background, and old ones are removed according to the
+//! retention settings.
+
+use crate::config::{LogConfig, Rotation};
+use crate::grpc::Compression;
+use chrono::{DateTime, Local};
+use flate2::write::GzEncoder;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+pub(crate) struct RollingWriter {
+    dir: PathBuf,
+    file_name: String,
+    rotation: Rotation,
+    max_size: u64,
+    file: File,
+    size: u64,
+    // Period the current file covers, for time-based rotation
+    period: Option<String>,
+    // Rotated files, compressed and cleaned up in the background
+    rotated: mpsc::Sender<PathBuf>,
+}
+
+impl RollingWriter {
+    pub(crate) fn new(dir: &str, file_name: &str, config: &LogConfig) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let dir = PathBuf::from(dir);
+        let file = open(&dir.join(file_name))?;
+        let metadata = file.metadata()?;
+
+        // A file left by a previous run covers the period it was last written in
+        let last_written = match metadata.modified() {
+            Ok(modified) if metadata.len() > 0 => DateTime::<Local>::from(modified),
+            _ => Local::now(),
+        };
+
+        let retention = Retention {
+            dir: dir.clone(),
+            file_name: file_name.to_string(),
+            compression: config.file_compression,
+            max_files: config.max_files,
+            max_age: config.max_age_secs.map(Duration::from_secs),
+            max_total_size: config.max_total_size_mb.map(|mb| mb * 1024 * 1024),
+        };
+        retention.remove_expired();
+        // Compress what a previous run rotated but did not get to
+        let pending = retention.uncompressed();
+        let rotated = retention.spawn()?;
+        for path in pending {
+            let _ = rotated.send(path);
+        }
+
+        Ok(Self {
+            file_name: file_name.to_string(),
+            rotation: config.rotation,
+            max_size: config.max_file_size_mb.max(1) * 1024 * 1024,
+            file,
+            size: metadata.len(),
+            period: period(config.rotation, last_written),
+            dir,
+            rotated,
+        })
+    }
+
+    fn should_rotate(&self, incoming: usize) -> bool {
+        match self.rotation {
+            Rotation::Never => false,
+            Rotation::Size => self.size > 0 && self.size + incoming as u64 > self.max_size,
+            Rotation::Minutely | Rotation::Hourly | Rotation::Daily => {
+                period(self.rotation, Local::now()) != self.period
+            }
+        }
+    }
+
+    /// Rename the current file after the period it covers, or the time of
+    /// rotation, and start a new one
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        let suffix = self
+            .period
+            .clone()
+            .unwrap_or_else(|| Local::now().format("%Y-%m-%d-%H-%M-%S").to_string());
+        let mut rotated = self.dir.join(format!("{}.{}", self.file_name, suffix));
+        let mut n = 1;
+        while taken(&rotated) {
+            rotated = self
+                .dir
+                .join(format!("{}.{}.{}", self.file_name, suffix, n));
+            n += 1;
+        }
+
+        let path = self.dir.join(&self.file_name);
+        fs::rename(&path, &rotated)?;
+        self.file = open(&path)?;
+        self.size = 0;
+        self.period = period(self.rotation, Local::now());
+
+        let _ = self.rotated.send(rotated);
+        Ok(())
+    }
+}
+
+/// What happens to rotated files: compression, then removal of the ones
+/// beyond the retention limits
+struct Retention {
+    dir: PathBuf,
+    file_name: String,
+    compression: Option<Compression>,
+    max_files: Option<usize>,
+    max_age: Option<Duration>,
+    max_total_size: Option<u64>,
+}
+
+impl Retention {
+    /// Start the thread processing rotated files as they are sent. It stops
+    /// once the writer is dropped.
+    fn spawn(self) -> io::Result<mpsc::Sender<PathBuf>> {
+        let (sender, receiver) = mpsc::channel::<PathBuf>();
+        thread::Builder::new()
+            .name("grpc-logger-rotate".to_string())
+            .spawn(move || {
+                for path in receiver {
+                    // Unless retention already removed it while it was queued
+                    if let Some(compression) = self.compression.filter(|_| path.exists()) {
+                        if let Err(e) = compress(&path, compression) {
+                            eprintln!(
+                                "GRPC Logger Error: Failed to compress {}: {}",
+                                path.display(),
+                                e
+                            );
+                        }
+                    }
+                    self.remove_expired();
+                }
+            })?;
+        Ok(sender)
+    }
+
+    /// Rotated files with their modification time and size, newest first.
+    /// Files modified at the same time are ordered by their name, then by
+    /// their counter.
+    fn rotated_files(&self) -> Vec<(SystemTime, PathBuf, u64)> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+
+        let prefix = format!("{}.", self.file_name);
+        let mut rotated: Vec<(SystemTime, PathBuf, u64)> = entries
+            .filter_map(Result::ok)
+            .filter(|entry| {
+                entry
+                    .file_name()
+                    .to_str()
+                    .is_some_and(|name| name.starts_with(&prefix))
+            })
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok()?;
+                Some((metadata.modified().ok()?, entry.path(), metadata.len()))
+            })
+            .collect();
+        // Compare counters as numbers, `.10` is newer than `.9`
+        rotated.sort_by_cached_key(|(modified, path, _)| {
+            std::cmp::Reverse((*modified, self.rotation_suffix(path)))
+        });
+        rotated
+    }
+
+    /// Period or time of rotation in the name of a rotated file, and the
+    /// counter added when that name was taken, e.g. `("2025-01-31", 10)` for
+    /// `app.log.2025-01-31.10.gz`
+    fn rotation_suffix(&self, path: &Path) -> (String, u64) {
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let suffix = name
+            .strip_prefix(&format!("{}.", self.file_name))
+            .unwrap_or(name);
+        let suffix = suffix
+            .strip_suffix(".gz")
+            .or_else(|| suffix.strip_suffix(".zst"))
+            .unwrap_or(suffix);
+        match suffix.rsplit_once('.') {
+            Some((period, counter)) => match counter.parse() {
+                Ok(counter) => (period.to_string(), counter),
+                Err(_) => (suffix.to_string(), 0),
+            },
+            None => (suffix.to_string(), 0),
+        }
+    }
+
+    /// Rotated files still to be compressed
+    fn uncompressed(&self) -> Vec<PathBuf> {
+        if self.compression.is_none() {
+            return Vec::new();
+        }
+        let mut pending: Vec<PathBuf> = self
+            .rotated_files()
+            .into_iter()
+            .map(|(_, path, _)| path)
+            .filter(|path| !is_compressed(path))
+            .collect();
+        pending.reverse();
+        pending
+    }
+
+    /// Remove the rotated files beyond `max_files` or `max_total_size`,
+    /// newest kept first, and the ones older than `max_age`. Files still to
+    /// be compressed don't count against `max_total_size` yet.
+    fn remove_expired(&self) {
+        if self.max_files.is_none() && self.max_age.is_none() && self.max_total_size.is_none() {
+            return;
+        }
+
+        let now = SystemTime::now();
+        let mut total_size = 0;
+        for (i, (modified, path, size)) in self.rotated_files().into_iter().enumerate() {
+            let pending = self.compression.is_some() && !is_compressed(&path);
+            if !pending {
+                total_size += size;
+            }
+            let extra = self.max_files.is_some_and(|max_files| i >= max_files);
+            let expired = self.max_age.is_some_and(|max_age| {
+                now.duration_since(modified)
+                    .is_ok_and(|age| age > max_age)
+            });
+            let over_size = !pending
+                && self
+                    .max_total_size
+                    .is_some_and(|max_total_size| total_size > max_total_size);
+            if extra || expired || over_size {
+                if let Err(e) = fs::remove_file(&path) {
+                    eprintln!(
+                        "GRPC Logger Error: Failed to remove old log file {}: {}",
+                        path.display(),
+                        e
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl Write for RollingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.should_rotate(buf.len()) {
+            // Keep writing to the current file rather than losing events, and
+            // try again at the next period or max size
+            if let Err(e) = self.rotate() {
+                eprintln!(
+                    "GRPC Logger Error: Failed to rotate {}: {}",
+                    self.dir.join(&self.file_name).display(),
+                    e
+                );
+                self.size = 0;
+                self.period = period(self.rotation, Local::now());
+            }
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Whether a rotated file of this name exists, compressed or not
+fn taken(path: &Path) -> bool {
+    path.exists()
+        || [Compression::Gzip, Compression::Zstd]
+            .into_iter()
+            .any(|compression| compressed_path(path, compression).exists())
+}
+
+fn is_compressed(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "gz" || extension == "zst")
+}
+
+fn compressed_path(path: &Path, compression: Compression) -> PathBuf {
+    let extension = match compression {
+        Compression::Gzip => "gz",
+        Compression::Zstd => "zst",
+    };
+    let mut compressed = path.as_os_str().to_owned();
+    compressed.push(".");
+    compressed.push(extension);
+    PathBuf::from(compressed)
+}
+
+/// Replace `path` with its compressed version
+fn compress(path: &Path, compression: Compression) -> io::Result<()> {
+    let compressed = compressed_path(path, compression);
+    let result = (|| {
+        let mut input = File::open(path)?;
+        // Retention goes by when the file was written, not compressed
+        let modified = input.metadata()?.modified()?;
+        let output = File::create(&compressed)?;
+        let output = match compression {
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(output, flate2::Compression::default());
+                io::copy(&mut input, &mut encoder)?;
+                encoder.finish()?
+            }
+            Compression::Zstd => {
+                zstd::stream::copy_encode(&mut input, &output, 0)?;
+                output
+            }
+        };
+        output.set_modified(modified)?;
+        output.sync_all()
+    })();
+
+    match result {
+        Ok(()) => fs::remove_file(path),
+        Err(e) => {
+            let _ = fs::remove_file(&compressed);
+            Err(e)
+        }
+    }
+}
+
+/// Period `time` falls in, also the suffix of the file rotated at its end.
+/// `None` when rotation is not time-based.
+fn period(rotation: Rotation, time: DateTime<Local>) -> Option<String> {
+    let format = match rotation {
+        Rotation::Minutely => "%Y-%m-%d-%H-%M",
+        Rotation::Hourly => "%Y-%m-%d-%H",
+        Rotation::Daily => "%Y-%m-%d",
+        Rotation::Never | Rotation::Size => return None,
+    };
+    Some(time.format(format).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Directory of rotated `app.log` files, removed when dropped
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "grpc-logger-rolling-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        /// Write a rotated file of `size` bytes, last modified `age_secs` ago
+        fn rotated(&self, suffix: &str, size: usize, age_secs: u64) {
+            let file = File::create(self.0.join(format!("app.log.{}", suffix))).unwrap();
+            file.set_len(size as u64).unwrap();
+            file.set_modified(SystemTime::now() - Duration::from_secs(age_secs))
+                .unwrap();
+        }
+
+        fn retention(&self) -> Retention {
+            Retention {
+                dir: self.0.clone(),
+                file_name: "app.log".to_string(),
+                compression: None,
+                max_files: None,
+                max_age: None,
+                max_total_size: None,
+            }
+        }
+
+        fn names(&self) -> Vec<String> {
+            self.retention()
+                .rotated_files()
+                .into_iter()
+                .map(|(_, path, _)| path.file_name().unwrap().to_str().unwrap().to_string())
+                .collect()
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn orders_counters_numerically() {
+        let dir = TestDir::new("counters");
+        let modified = SystemTime::now();
+        for suffix in ["x", "x.1", "x.2", "x.9", "x.10.gz", "x.11"] {
+            let file = File::create(dir.0.join(format!("app.log.{}", suffix))).unwrap();
+            file.set_modified(modified).unwrap();
+        }
+        File::create(dir.0.join("app.log")).unwrap();
+
+        assert_eq!(
+            dir.names(),
+            [
+                "app.log.x.11",
+                "app.log.x.10.gz",
+                "app.log.x.9",
+                "app.log.x.2",
+                "app.log.x.1",
+                "app.log.x",
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_newest_max_files() {
+        let dir = TestDir::new("max-files");
+        dir.rotated("a", 1, 30);
+        dir.rotated("b", 1, 20);
+        dir.rotated("c", 1, 10);
+
+        Retention {
+            max_files: Some(2),
+            ..dir.retention()
+        }
+        .remove_expired();
+        assert_eq!(dir.names(), ["app.log.c", "app.log.b"]);
+    }
+
+    #[test]
+    fn removes_files_older_than_max_age() {
+        let dir = TestDir::new("max-age");
+        dir.rotated("a", 1, 3600);
+        dir.rotated("b", 1, 10);
+
+        Retention {
+            max_age: Some(Duration::from_secs(60)),
+            ..dir.retention()
+        }
+        .remove_expired();
+        assert_eq!(dir.names(), ["app.log.b"]);
+    }
+
+    #[test]
+    fn removes_oldest_beyond_max_total_size() {
+        let dir = TestDir::new("max-size");
+        dir.rotated("a", 400, 30);
+        dir.rotated("b", 400, 20);
+        dir.rotated("c", 400, 10);
+
+        Retention {
+            max_total_size: Some(1000),
+            ..dir.retention()
+        }
+        .remove_expired();
+        assert_eq!(dir.names(), ["app.log.c", "app.log.b"]);
+    }
+
+    #[test]
+    fn max_total_size_waits_for_compression() {
+        let dir = TestDir::new("max-size-pending");
+        dir.rotated("a.gz", 400, 30);
+        dir.rotated("b", 4000, 20);
+        dir.rotated("c.gz", 400, 10);
+
+        Retention {
+            compression: Some(Compression::Gzip),
+            max_total_size: Some(1000),
+            ..dir.retention()
+        }
+        .remove_expired();
+        assert_eq!(dir.names(), ["app.log.c.gz", "app.log.b", "app.log.a.gz"]);
+    }
+
+    #[test]
+    fn keeps_everything_without_limits() {
+        let dir = TestDir::new("no-limits");
+        dir.rotated("a", 1, 3600 * 24 * 365);
+        dir.rotated("b", 1, 10);
+
+        dir.retention().remove_expired();
+        assert_eq!(dir.names(), ["app.log.b", "app.log.a"]);
+    }
+
+    #[test]
+    fn compresses_and_keeps_modification_time() {
+        let dir = TestDir::new("compress");
+        let content = "line\n".repeat(1000);
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let path = dir.0.join("app.log.a");
+            fs::write(&path, &content).unwrap();
+            let modified = SystemTime::now() - Duration::from_secs(3600);
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+
+            compress(&path, compression).unwrap();
+            assert!(!path.exists());
+            let compressed = compressed_path(&path, compression);
+            assert_eq!(
+                fs::metadata(&compressed).unwrap().modified().unwrap(),
+                modified
+            );
+
+            let file = File::open(&compressed).unwrap();
+            let decompressed = match compression {
+                Compression::Gzip => {
+                    let mut decoder = flate2::read::GzDecoder::new(file);
+                    let mut decompressed = Vec::new();
+                    io::Read::read_to_end(&mut decoder, &mut decompressed).unwrap();
+                    decompressed
+                }
+                Compression::Zstd => zstd::stream::decode_all(file).unwrap(),
+            };
+            assert_eq!(decompressed, content.as_bytes());
+            fs::remove_file(compressed).unwrap();
+        }
+    }
+
+    #[test]
+    fn rotates_at_max_size() {
+        let dir = TestDir::new("rotate-size");
+        let config = LogConfig {
+            rotation: Rotation::Size,
+            max_file_size_mb: 1,
+            ..LogConfig::default()
+        };
+        let mut writer = RollingWriter::new(dir.0.to_str().unwrap(), "app.log", &config).unwrap();
+        let chunk = vec![b'a'; 700 * 1024];
+        writer.write_all(&chunk).unwrap();
+        assert!(dir.names().is_empty());
+
+        writer.write_all(&chunk).unwrap();
+        writer.flush().unwrap();
+        let names = dir.names();
+        assert_eq!(names.len(), 1);
+        assert_eq!(
+            fs::metadata(dir.0.join(&names[0])).unwrap().len(),
+            chunk.len() as u64
+        );
+        assert_eq!(
+            fs::metadata(dir.0.join("app.log")).unwrap().len(),
+            chunk.len() as u64
+        );
+    }
+
+    #[test]
+    fn adds_a_counter_when_the_rotated_name_is_taken() {
+        let dir = TestDir::new("rotate-counter");
+        let config = LogConfig {
+            rotation: Rotation::Daily,
+            ..LogConfig::default()
+        };
+        let mut writer = RollingWriter::new(dir.0.to_str().unwrap(), "app.log", &config).unwrap();
+        let today = period(Rotation::Daily, Local::now()).unwrap();
+        writer.write_all(b"first").unwrap();
+        writer.rotate().unwrap();
+        writer.write_all(b"second").unwrap();
+        writer.rotate().unwrap();
+
+        let first = dir.0.join(format!("app.log.{}", today));
+        let second = dir.0.join(format!("app.log.{}.1", today));
+        assert_eq!(fs::read(first).unwrap(), b"first");
+        assert_eq!(fs::read(second).unwrap(), b"second");
+        assert_eq!(fs::metadata(dir.0.join("app.log")).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn compresses_rotated_files_in_the_background() {
+        let dir = TestDir::new("rotate-compress");
+        let config = LogConfig {
+            rotation: Rotation::Daily,
+            file_compression: Some(Compression::Gzip),
+            ..LogConfig::default()
+        };
+        let mut writer = RollingWriter::new(dir.0.to_str().unwrap(), "app.log", &config).unwrap();
+        writer.write_all(b"rotated").unwrap();
+        writer.rotate().unwrap();
+
+        let rotated = dir.0.join(format!(
+            "app.log.{}",
+            period(Rotation::Daily, Local::now()).unwrap()
+        ));
+        let compressed = compressed_path(&rotated, Compression::Gzip);
+        for _ in 0..100 {
+            if compressed.exists() && !rotated.exists() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert!(!rotated.exists());
+        assert!(compressed.exists());
+    }
+}
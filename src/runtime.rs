@@ -0,0 +1,35 @@
+//! Background tasks that must run whether or not the caller is inside a Tokio
+//! runtime, so that events emitted from plain threads still get delivered.
+
+use std::future::Future;
+
+/// Spawn `task` on the current Tokio runtime, or, outside of one, on a thread
+/// named `name` driving a runtime of its own until the task completes.
+pub(crate) fn spawn<F>(name: &str, task: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    if let Ok(handle) = tokio::runtime::Handle::try_current() {
+        handle.spawn(task);
+        return;
+    }
+
+    let thread_name = name.to_string();
+    let spawned = std::thread::Builder::new()
+        .name(thread_name.clone())
+        .spawn(move || {
+            match tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(runtime) => runtime.block_on(task),
+                Err(e) => eprintln!(
+                    "GRPC Logger Error: Failed to start a runtime for {}: {}",
+                    thread_name, e
+                ),
+            }
+        });
+    if let Err(e) = spawned {
+        eprintln!("GRPC Logger Error: Failed to start {}: {}", name, e);
+    }
+}
@@ -1,36 +1,143 @@
-use crate::config::{LogConfig, LogOutput};
-use crate::setup_logging::setup_logging;
+use crate::config::{LogConfig, LogOutput, QueueConfig};
+use crate::auth::{self, TokenAuth};
+use crate::ConnectionInfo;
+use crate::filter::SubscriptionFilter;
+use crate::grpc::TRACEPARENT;
+use crate::history::{HistoryQuery, LogHistory};
+use crate::setup_logging::{setup_logging_internal, FilterHandle};
+use crate::convert::{from_timestamp, to_timestamp};
+use crate::legacy::LegacyLogService;
+use crate::queue::{PushError, SubscriberQueue};
+use crate::runtime;
+use chrono::{DateTime, Utc};
 use futures::Stream;
 use futures::StreamExt;
 use std::collections::HashMap;
 use std::pin::Pin;
-use tokio::sync::mpsc;
-use tonic::{Request, Response, Status};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use tokio::sync::{mpsc, oneshot};
+use tonic::{Request, Response, Status, Streaming};
 use tonic_web::GrpcWebLayer;
-use tracing::{info, trace, warn};
+use tracing::{debug, info, trace, warn};
+use tracing_subscriber::EnvFilter;
+use uuid::Uuid;
 
-use crate::server_build::logging::ClientType;
 pub mod logging {
     tonic::include_proto!("logging");
+
+    pub mod v2 {
+        tonic::include_proto!("logging.v2");
+    }
 }
-use logging::log_service_server::LogService;
-use logging::log_service_server::LogServiceServer;
-use logging::{LogMessage, SubscribeRequest};
+use logging::v2::log_service_server::LogService;
+use logging::v2::admin_service_server::AdminServiceServer;
+use logging::v2::log_service_server::LogServiceServer;
+use logging::v2::{
+    ClientType, ControlRequest, ControlResponse, Level, LogMessage, PushLogsResponse, QueryLogsRequest, QueryLogsResponse,
+    LogBatch, SequenceGap, SubscribeRequest, WatchRequest,
+};
+use logging::v2::{control_request, watch_request};
 use std::sync::Arc;
 use tokio::sync::Mutex;
+use tracing_appender::non_blocking::WorkerGuard;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use tonic::server::NamedService;
+use tonic::service::interceptor::InterceptedService;
+use tonic::transport::server::TcpIncoming;
 use tonic::transport::Server;
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
 use tonic_reflection::server::Builder;
-use tower_http::cors::{Any, CorsLayer};
+use tower_http::cors::{AllowHeaders, Any, CorsLayer};
+
+type LogStream = Pin<Box<dyn Stream<Item = Result<LogMessage, Status>> + Send>>;
+type LogBatchStream = Pin<Box<dyn Stream<Item = Result<LogBatch, Status>> + Send>>;
+
+// Messages delivered per acquisition of the clients lock
+const DISPATCH_BATCH: usize = 256;
+/// Response metadata of a subscription holding the token that lets
+/// `ControlSubscription` steer it
+pub const CONTROL_TOKEN: &str = "control-token";
+
+const DEFAULT_BATCH_MESSAGES: usize = 256;
+const MAX_BATCH_MESSAGES: usize = 4096;
+const DEFAULT_BATCH_DELAY_MS: u64 = 50;
+
+type ServerHandle =
+    tokio::task::JoinHandle<Result<(), Box<dyn std::error::Error + Send + Sync>>>;
+
+/// Work items of the dispatcher task
+#[derive(Debug)]
+enum Dispatch {
+    Log(Box<LogMessage>),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Delivery counters of a subscriber, shared with its outgoing stream
+#[derive(Debug, Default)]
+struct ClientStats {
+    sent: AtomicU64,
+    // Status the stream ends with when the server closes it
+    close_status: std::sync::Mutex<Option<Status>>,
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    queue: Arc<SubscriberQueue>,
+    filter: SubscriptionFilter,
+    info: ConnectionInfo,
+    stats: Arc<ClientStats>,
+    // Latest sequences when the client paused, None while streaming live
+    paused: Option<HashMap<String, u64>>,
+    // Proves a ControlSubscription caller opened this subscription
+    control_token: String,
+    // Dropping it ends the client's stream without waiting for the queue
+    _close: oneshot::Sender<()>,
+}
+
+impl Subscriber {
+    /// Whether the client should receive `log`, live or replayed
+    fn is_visible(&self, log: &LogMessage) -> bool {
+        self.filter.matches(log)
+            && log
+                .target_client_id
+                .as_ref()
+                .is_none_or(|target| *target == self.info.client_id)
+    }
+
+    fn close_with(&self, status: Status) {
+        *self.stats.close_status.lock().unwrap() = Some(status);
+    }
+
+    /// Queue `log` for the client. Fails once the client must be removed.
+    fn send(&self, log: LogMessage) -> Result<(), PushError> {
+        self.queue.push(log).inspect_err(|e| {
+            if *e == PushError::Full {
+                warn!("Disconnecting slow client {}: queue full", self.info.client_id);
+                self.close_with(Status::resource_exhausted(
+                    "Disconnected for not keeping up with the stream",
+                ));
+            }
+        })
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct LoggingService {
-    clients: Arc<Mutex<HashMap<String, mpsc::UnboundedSender<LogMessage>>>>,
-    server_handle: Arc<
-        Mutex<
-            Option<tokio::task::JoinHandle<Result<(), Box<dyn std::error::Error + Send + Sync>>>>,
-        >,
-    >,
-    log_all_messages: Arc<Mutex<bool>>,
+    clients: Arc<Mutex<HashMap<String, Subscriber>>>,
+    server_handle: Arc<Mutex<Option<ServerHandle>>>,
+    // Keeps the file outputs flushing
+    log_guards: Arc<Mutex<Vec<WorkerGuard>>>,
+    log_all_messages: Arc<AtomicBool>,
+    dispatcher: mpsc::UnboundedSender<Dispatch>,
+    history: Arc<Mutex<LogHistory>>,
+    // Set while the dispatcher task delivers messages to subscribers
+    pipeline_running: Arc<AtomicBool>,
+    health: Arc<Mutex<Option<HealthReporter>>>,
+    queue_config: Arc<Mutex<QueueConfig>>,
+    // Filters of the outputs, in the order of the output list
+    filter_handles: Arc<Mutex<Vec<FilterHandle>>>,
 }
 
 impl Default for LoggingService {
@@ -41,11 +148,32 @@ impl Default for LoggingService {
 
 impl LoggingService {
     pub fn new() -> Self {
-        Self {
+        let (dispatcher, dispatch_receiver) = mpsc::unbounded_channel();
+        let service = Self {
             clients: Arc::new(Mutex::new(HashMap::new())),
             server_handle: Arc::new(Mutex::new(None)),
-            log_all_messages: Arc::new(Mutex::new(false)), // Default to false
-        }
+            log_guards: Arc::new(Mutex::new(Vec::new())),
+            log_all_messages: Arc::new(AtomicBool::new(false)), // Default to false
+            dispatcher,
+            history: Arc::new(Mutex::new(LogHistory::new(
+                LogConfig::default().history_size,
+            ))),
+            pipeline_running: Arc::new(AtomicBool::new(false)),
+            health: Arc::new(Mutex::new(None)),
+            queue_config: Arc::new(Mutex::new(QueueConfig::default())),
+            filter_handles: Arc::new(Mutex::new(Vec::new())),
+        };
+        let dispatcher = Dispatcher {
+            clients: service.clients.clone(),
+            history: service.history.clone(),
+            log_all_messages: service.log_all_messages.clone(),
+            pipeline_running: service.pipeline_running.clone(),
+            health: service.health.clone(),
+        };
+        // On the runtime the service is created in, or on a thread of its own
+        // when created outside of one
+        runtime::spawn("grpc-logger-dispatch", dispatcher.run(dispatch_receiver));
+        service
     }
 
     /// Initialize the entire logging service, including setting up logging and starting the server
@@ -54,37 +182,46 @@ impl LoggingService {
         config: &LogConfig,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         // Set log_all_messages from config
-        {
-            let mut log_all = self.log_all_messages.lock().await;
-            *log_all = config.log_all_messages;
+        self.log_all_messages
+            .store(config.log_all_messages, Ordering::Relaxed);
+        self.history.lock().await.set_capacity(config.history_size);
+        *self.queue_config.lock().await = config.subscriber_queue.clone();
+        // Setup logging first, broadcasting our own events to subscribers.
+        // Skipped when re-initializing, the subscriber can only be installed once.
+        if !tracing::dispatcher::has_been_set() {
+            let guards = setup_logging_internal(config, Some(self.clone())).await?;
+            *self.log_guards.lock().await = guards;
         }
-        // Setup logging first
-        // let service_clone = self.clone();
-        let _guard = setup_logging(config);
 
         // Log initialization details
-        info!("Logger initialized with output: {:?}", config.output);
-        match &config.output {
-            LogOutput::File => {
-                info!(
-                    "File logging enabled - path: {}, filename: {}",
-                    config.file_path.as_deref().unwrap_or("default"),
-                    config.file_name.as_deref().unwrap_or("app.log")
-                );
-            }
-            LogOutput::Grpc => {
-                if let Some(grpc_config) = &config.grpc {
+        for output in &config.output {
+            let level = output.level.as_deref().unwrap_or(&config.level);
+            match output.output {
+                LogOutput::File => {
                     info!(
-                        "GRPC logging enabled - server running on {}:{}",
-                        grpc_config.address, grpc_config.port
+                        "File logging enabled at {} - path: {}, filename: {}",
+                        level,
+                        output
+                            .file_path
+                            .as_deref()
+                            .or(config.file_path.as_deref())
+                            .unwrap_or("logs"),
+                        config.file_name(output)
                     );
                 }
-            }
-            LogOutput::Console => {
-                info!("Console logging enabled");
+                LogOutput::Grpc => {
+                    if let Some(grpc_config) = &config.grpc {
+                        info!(
+                            "GRPC logging enabled at {} - server running on {}:{}",
+                            level, grpc_config.address, grpc_config.port
+                        );
+                    }
+                }
+                LogOutput::Console => {
+                    info!("Console logging enabled at {}", level);
+                }
             }
         }
-        info!("Log level set to: {}", config.level);
 
         // Start test log generation
         // let service_clone1 = self.clone();
@@ -116,47 +253,114 @@ impl LoggingService {
         &self,
         config: &LogConfig,
     ) -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
-        let addr = match &config.grpc {
+        let addr: SocketAddr = match &config.grpc {
             Some(grpc_config) => format!("{}:{}", grpc_config.address, grpc_config.port),
             None => "0.0.0.0:50052".to_string(),
         }
         .parse()?;
 
+        let mut server = Server::builder();
+        if let Some(tls) = config.grpc.as_ref().and_then(|grpc| grpc.tls.as_ref()) {
+            server = server.tls_config(tls.server_config()?)?;
+            if tls.client_ca.is_some() {
+                info!("Serving over TLS, client certificates required");
+            } else {
+                info!("Serving over TLS");
+            }
+        }
+
+        // Bind here so that failures reach the caller instead of the server task
+        let listener = match TcpListener::bind(addr).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                if e.kind() == std::io::ErrorKind::AddrInUse {
+                    tracing::error!("Port already in use. Please stop other instances first.");
+                }
+                return Err(e.into());
+            }
+        };
+        let incoming =
+            TcpIncoming::from_listener(listener, true, Some(std::time::Duration::from_secs(60)))?;
+
+        let (health_reporter, health_service) = tonic_health::server::health_reporter();
+        *self.health.lock().await = Some(health_reporter);
+        self.report_health(self.pipeline_running.load(Ordering::SeqCst))
+            .await;
+
         let descriptor_set = include_bytes!(concat!(env!("OUT_DIR"), "/logging_descriptor.bin"));
         let reflection_service = Builder::configure()
             .register_encoded_file_descriptor_set(descriptor_set)
             .build_v1()?;
 
-        // Create CORS layer
+        // Create CORS layer. A wildcard doesn't cover the authorization header,
+        // so grpc-web clients' requested headers are mirrored instead.
         let cors = CorsLayer::new()
             .allow_origin(Any)
-            .allow_headers(Any)
+            .allow_headers(AllowHeaders::mirror_request())
             .allow_methods(Any)
             .expose_headers(Any);
 
+        // Compression is negotiated per call, from the client's grpc-accept-encoding
+        let compression = config
+            .grpc
+            .as_ref()
+            .map(|grpc_config| grpc_config.compression.clone())
+            .unwrap_or_default();
+        let mut log_service = LogServiceServer::new(self.clone());
+        let mut legacy_log_service =
+            logging::log_service_server::LogServiceServer::new(LegacyLogService::new(self.clone()));
+        for &encoding in &compression.accept {
+            log_service = log_service.accept_compressed(encoding.into());
+            legacy_log_service = legacy_log_service.accept_compressed(encoding.into());
+        }
+        for &encoding in &compression.send {
+            log_service = log_service.send_compressed(encoding.into());
+            legacy_log_service = legacy_log_service.send_compressed(encoding.into());
+        }
+
+        let auth_config = config.grpc.as_ref().and_then(|grpc| grpc.auth.as_ref());
+        let token_auth = TokenAuth::new(auth_config);
+        if token_auth.is_enabled() {
+            info!("Bearer token authentication enabled");
+        }
+        // Admin calls change the filters and disconnect clients, a token
+        // allowed to read logs is not enough
+        let admin_auth = TokenAuth::admin(auth_config);
+        if !admin_auth.has_tokens() {
+            warn!("AdminService refuses every call: no grpc.auth.admin_tokens configured");
+        }
+
         let service = self.clone();
         let handle = tokio::spawn(async move {
-            match Server::builder()
+            match server
                 .accept_http1(true)
                 .max_concurrent_streams(128) // Set reasonable limits
                 .tcp_keepalive(Some(std::time::Duration::from_secs(60)))
                 .tcp_nodelay(true)
+                .trace_fn(request_span)
                 .layer(cors) // Add CORS layer
                 .layer(GrpcWebLayer::new())
-                .add_service(LogServiceServer::new(service))
+                .add_service(InterceptedService::new(log_service, token_auth.clone()))
+                .add_service(AdminServiceServer::with_interceptor(
+                    service.clone(),
+                    admin_auth,
+                ))
+                .add_service(InterceptedService::new(legacy_log_service, token_auth))
                 .add_service(reflection_service) // Add reflection service
-                // .serve(addr)
-                .serve_with_shutdown(addr, async {
+                .add_service(health_service)
+                .serve_with_incoming_shutdown(incoming, async {
                     tokio::signal::ctrl_c().await.ok();
                     info!("Shutting down server...");
                 })
                 .await
             {
-                Ok(_) => Ok(()),
+                Ok(_) => {
+                    service.report_health(false).await;
+                    Ok(())
+                }
                 Err(e) => {
-                    if e.to_string().contains("Address already in use") {
-                        tracing::error!("Port already in use. Please stop other instances first.");
-                    }
+                    tracing::error!("gRPC server stopped: {}", e);
+                    service.report_health(false).await;
                     Err(e.into())
                 }
             }
@@ -167,92 +371,204 @@ impl LoggingService {
         Ok(())
     }
 
-    pub async fn broadcast_log_filtered(&self, log: LogMessage, client_id: String) {
-        let clients = self.clients.lock().await;
-        if let Some(sender) = clients.get(&client_id) {
-            let _ = sender.send(log);
-        }
+    /// Same as [`send_to_client`](Self::send_to_client)
+    pub async fn broadcast_log_filtered(&self, log: LogMessage, client_id: String) -> bool {
+        self.send_to_client(&client_id, log).await
+    }
+
+    /// Deliver `log` to the subscriber `client_id` only.
+    ///
+    /// Returns whether that client is currently connected. The message is
+    /// retained either way, so the client gets it on resume or history replay.
+    pub async fn send_to_client(&self, client_id: &str, mut log: LogMessage) -> bool {
+        log.target_client_id = Some(client_id.to_string());
+        self.publish(log).await
+    }
+
+    /// Whether a subscriber with this id is connected
+    pub async fn is_client_connected(&self, client_id: &str) -> bool {
+        self.clients.lock().await.contains_key(client_id)
     }
 
+    /// Deliver `log` to every subscriber, or only to the one in its
+    /// `target_client_id`
     pub async fn broadcast_log(&self, log: LogMessage) {
-        let clients = self.clients.lock().await;
-        let log_all = self.log_all_messages.lock().await;
-        let mut dead_clients = Vec::new();
+        self.publish(log).await;
+    }
+
+    /// Returns false when the message is targeted at a client that isn't connected
+    async fn publish(&self, log: LogMessage) -> bool {
+        let mut clients = self.clients.lock().await;
+        let mut history = self.history.lock().await;
+        let log_all = self.log_all_messages.load(Ordering::Relaxed);
+        let connected = deliver(&clients, &mut history, log, log_all);
+        drop(history);
+        remove_dead_clients(&mut clients);
+        connected
+    }
+
+    /// Queue a message for the dispatcher task, which delivers messages one
+    /// at a time in the order they were dispatched.
+    ///
+    /// Never blocks and takes no lock, and works from any thread, inside a
+    /// Tokio runtime or not. This is what `GrpcLayer` uses for every event.
+    pub fn dispatch(&self, log: LogMessage) {
+        let _ = self.dispatcher.send(Dispatch::Log(Box::new(log)));
+    }
 
-        // Skip internal messages unless explicitly configured to log all
-        if !*log_all && is_internal_message(&log) {
-            return;
+    /// Wait until every message dispatched so far has been delivered
+    pub async fn flush(&self) {
+        let (done_tx, done_rx) = oneshot::channel();
+        if self.dispatcher.send(Dispatch::Flush(done_tx)).is_ok() {
+            let _ = done_rx.await;
         }
+    }
 
-        for (client_id, sender) in clients.iter() {
-            // Check if message is targeted
-            if let Some(target_id) = &log.target_client_id {
-                // Only send if this is the target client
-                if target_id == client_id {
-                    if let Err(_) = sender.send(log.clone()) {
-                        dead_clients.push(client_id.clone());
-                    }
-                }
-            } else {
-                // Broadcast to all if no target specified
-                if *log_all || !is_internal_message(&log) {
-                    if let Err(_) = sender.send(log.clone()) {
-                        dead_clients.push(client_id.clone());
-                    }
-                }
-            }
+    /// Publish the serving status of every service through `grpc.health.v1.Health`
+    async fn report_health(&self, serving: bool) {
+        report_health(&self.health, serving).await;
+    }
+
+    pub(crate) async fn set_filter_handles(&self, handles: Vec<FilterHandle>) {
+        *self.filter_handles.lock().await = handles;
+    }
+
+    /// Directives of the filter of each output, in the order of the `output`
+    /// list and in `EnvFilter` syntax. Empty when this service did not set up
+    /// logging.
+    pub async fn output_filters(&self) -> Vec<String> {
+        let handles = self.filter_handles.lock().await;
+        handles
+            .iter()
+            .filter_map(|handle| handle.with_current(|filter| filter.to_string()).ok())
+            .collect()
+    }
+
+    /// Directives of the filter currently applied to every output, in
+    /// `EnvFilter` syntax. Empty while the outputs filter at the different
+    /// levels they were configured with, see `output_filters`. `None` when
+    /// this service did not set up logging.
+    pub async fn get_filter(&self) -> Option<String> {
+        shared_filter(&self.output_filters().await)
+    }
+
+    /// Replace the filter of every output, e.g. with
+    /// `EnvFilter::builder().parse("info,my_crate::db=debug")`. It replaces
+    /// their configured levels too, so it can make any output more verbose.
+    ///
+    /// Returns the previous directives, empty if the outputs had different
+    /// ones: take `output_filters` first to restore those with
+    /// `set_output_filters`.
+    pub async fn set_filter(
+        &self,
+        filter: EnvFilter,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let outputs = self.filter_handles.lock().await.len();
+        // EnvFilter can't be cloned, the other outputs get it parsed again
+        let directives = filter.to_string();
+        let mut filters = vec![filter];
+        for _ in 1..outputs {
+            filters.push(EnvFilter::builder().parse(&directives)?);
         }
 
-        // Clean up disconnected clients
-        drop(clients);
-        if !dead_clients.is_empty() {
-            let mut clients = self.clients.lock().await;
-            for client_id in dead_clients {
-                clients.remove(&client_id);
-                warn!("Removed disconnected client: {}", client_id);
-            }
+        let previous = self.set_output_filters(filters).await?;
+        Ok(shared_filter(&previous).unwrap_or_default())
+    }
+
+    /// Replace the filter of each output, one per output in the order of the
+    /// `output` list. Returns the previous ones, in the same order, so that
+    /// they can be restored.
+    pub async fn set_output_filters(
+        &self,
+        filters: Vec<EnvFilter>,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let handles = self.filter_handles.lock().await;
+        if handles.is_empty() {
+            return Err("Logging was not set up by this service".into());
+        }
+        if filters.len() != handles.len() {
+            return Err(format!(
+                "Expected {} output filters, got {}",
+                handles.len(),
+                filters.len()
+            )
+            .into());
+        }
+
+        let new: Vec<String> = filters.iter().map(ToString::to_string).collect();
+        let mut previous = Vec::with_capacity(handles.len());
+        for (handle, filter) in handles.iter().zip(filters) {
+            handle.modify(|current| {
+                previous.push(current.to_string());
+                *current = filter;
+            })?;
         }
+
+        info!("Log filters changed from {:?} to {:?}", previous, new);
+        Ok(previous)
     }
 
-    pub async fn check_connection(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        // Try to acquire the lock on clients - if we can't, server might be down
-        if let Ok(clients) = self.clients.try_lock() {
-            // Check if we have any active clients
-            if !clients.is_empty() {
-                return Ok(());
+    /// Clients currently subscribed, with their delivery counters
+    pub async fn list_clients(&self) -> Vec<ConnectionInfo> {
+        let clients = self.clients.lock().await;
+        clients
+            .values()
+            .map(|subscriber| ConnectionInfo {
+                messages_sent: subscriber.stats.sent.load(Ordering::Relaxed),
+                queue_depth: subscriber.queue.len() as u64,
+                paused: subscriber.paused.is_some(),
+                ..subscriber.info.clone()
+            })
+            .collect()
+    }
+
+    /// Close a client's stream right away, dropping its queued messages.
+    /// Returns false if no such client is connected.
+    pub async fn disconnect_client(&self, client_id: &str) -> bool {
+        let removed = self.clients.lock().await.remove(client_id);
+        match removed {
+            Some(subscriber) => {
+                subscriber.close_with(Status::aborted("Disconnected by administrator"));
+                warn!("Disconnected client: {}", client_id);
+                true
             }
+            None => false,
         }
-
-        // If we got here, either lock failed or no clients - consider connection dead
-        Err("Connection lost to logging server".into())
     }
-}
 
-#[tonic::async_trait]
-impl LogService for LoggingService {
-    type SubscribeToLogsStream = Pin<Box<dyn Stream<Item = Result<LogMessage, Status>> + Send>>;
-    async fn subscribe_to_logs(
+    /// Register a subscriber and build its outgoing stream, shared by
+    /// SubscribeToLogs and Watch. Also returns the subscriber's control token.
+    async fn subscribe(
         &self,
-        request: Request<SubscribeRequest>,
-    ) -> Result<Response<Self::SubscribeToLogsStream>, Status> {
-        println!("→ Entering subscribe_to_logs");
+        request_inner: SubscribeRequest,
+        peer_address: Option<SocketAddr>,
+        identity: Option<String>,
+    ) -> Result<(LogStream, Arc<ClientStats>, String), Status> {
         info!("Starting new log subscription request");
+        if request_inner.batch.is_some() {
+            return Err(Status::invalid_argument(
+                "batch is only supported by SubscribeToLogBatches",
+            ));
+        }
 
-        let metadata = request.metadata();
-        println!("  Metadata received: {:?}", metadata);
-
-        let request_inner = request.into_inner();
         let client_id = request_inner.client_id;
         println!("  Extracted client_id: {}", client_id);
 
-        let client_type = ClientType::from_i32(request_inner.client_type)
+        let client_type = ClientType::try_from(request_inner.client_type)
             .unwrap_or(ClientType::Unknown);
         println!("  Client type resolved to: {:?}", client_type);
 
+        let filter = SubscriptionFilter::from_proto(request_inner.filter)?;
+        debug!("Subscription filter of {}: {:?}", client_id, filter);
+
+        let history_last = request_inner.history_last.map(|n| n as usize);
+        let history_since = timestamp_arg("history_since", request_inner.history_since)?;
+
+        let server_name = request_inner.server_name;
+
         // Log different messages based on client type
         match client_type {
             ClientType::Server => {
-                let server_name = request_inner.server_name;
                 println!("  Processing Server client type");
                 info!(
                     "🔧 Server instance connected: {} (name: {})",
@@ -269,77 +585,630 @@ impl LogService for LoggingService {
             }
         }
 
-        // Create a channel for this specific client
-        let (tx, rx) = mpsc::unbounded_channel();
-        println!("  Channel created for client");
+        // Create a bounded queue for this specific client
+        let (queue, receiver) = {
+            let queue_config = self.queue_config.lock().await;
+            SubscriberQueue::new(queue_config.capacity, queue_config.policy(client_type))
+        };
+        let (close_tx, close_rx) = oneshot::channel();
+        let stats = Arc::new(ClientStats::default());
+        let subscriber = Subscriber {
+            queue,
+            filter,
+            info: ConnectionInfo {
+                client_id: client_id.clone(),
+                client_type,
+                server_name,
+                peer_address,
+                identity,
+                connected_at: Utc::now(),
+                messages_sent: 0,
+                queue_depth: 0,
+                paused: false,
+                acked_sequences: HashMap::new(),
+            },
+            stats: stats.clone(),
+            paused: None,
+            control_token: Uuid::new_v4().simple().to_string(),
+            _close: close_tx,
+        };
+        debug!("Queue created for client {}", client_id);
 
-        // Store the sender in our clients map
+        // Replay retained history, then store the sender in our clients map
+        let control_token;
         {
             println!("  Attempting to acquire clients lock");
             let mut clients = self.clients.lock().await;
             println!("  Lock acquired, inserting client");
-            clients.insert(client_id.clone(), tx);
+            let visible = |log: &LogMessage| subscriber.is_visible(log);
+            let replay = if let Some(resume) = &request_inner.resume {
+                let (replay, gaps) = self
+                    .history
+                    .lock()
+                    .await
+                    .resume(&resume.last_sequences, &resume.epoch, visible);
+                debug!(
+                    "Resuming {} with {} retained messages and {} gaps",
+                    client_id,
+                    replay.len(),
+                    gaps.len()
+                );
+                gaps.into_iter().map(gap_message).chain(replay).collect()
+            } else if history_last.is_some() || history_since.is_some() {
+                let replay = self.history.lock().await.replay(
+                    history_last,
+                    history_since,
+                    visible,
+                );
+                debug!("Replaying {} retained messages to {}", replay.len(), client_id);
+                replay
+            } else {
+                Vec::new()
+            };
+            for log in replay {
+                if subscriber.send(log).is_err() {
+                    return Err(Status::resource_exhausted(
+                        "Replay exceeds the subscriber queue capacity",
+                    ));
+                }
+            }
+            control_token = subscriber.control_token.clone();
+            // Replacing a client with the same id closes its previous stream
+            clients.insert(client_id.clone(), subscriber);
             info!("Added new client {} to clients map", client_id);
         }
 
-        // Convert receiver into a stream
-        let stream = tokio_stream::wrappers::UnboundedReceiverStream::new(rx);
-        println!("  Stream created from receiver");
-
-        // Only send test message to web clients
-        if client_type == ClientType::WebClient {
-            println!("  Preparing test message for web client");
-            let test_message = LogMessage {
-                target_client_id: None,
-                server_id: None,
-                timestamp: Some(chrono::Utc::now().to_rfc3339()),
-                level: Some("INFO".to_string()),
-                message: format!("Test message for web client {}", client_id),
-                target: None,
-                thread_id: None,
-                file: None,
-                line: None,
-            };
-            println!("  Broadcasting test message");
-            self.broadcast_log(test_message).await;
-            info!("Sent test message to web client {}", client_id);
-        }
+        // Convert receiver into a stream, it ends when close_rx fires
+        let stream = futures::stream::unfold(receiver, |receiver| async move {
+            let log = receiver.recv().await;
+            Some((log, receiver))
+        });
 
         let client_id_for_end = client_id.clone();
         let client_id_for_log = client_id.clone();
+        let stats_for_log = stats.clone();
+        let stats_for_end = stats.clone();
         println!("  Setting up mapped stream");
 
         let mapped_stream = Box::pin(
             stream
+                .take_until(close_rx)
                 .map(move |result| {
                     println!("  Processing stream message for client {}", client_id_for_log);
-                    // Log when sending a message
-                    if let Some(target) = &result.target {
-                        if !target.starts_with("h2::")
-                            && !target.starts_with("tonic::")
-                            && !target.starts_with("tonic_web::")
-                        {
-                            info!(
-                                "📤 Sending log to client {}: {:?}",
-                                client_id_for_log, result
-                            );
-                        }
-                    }
+                    stats_for_log.sent.fetch_add(1, Ordering::Relaxed);
                     Ok(result)
                 })
                 .chain(futures::stream::once(async move {
                     println!("  Stream ending for client {}", client_id_for_end);
                     info!("🏁 Stream ending for client {}", client_id_for_end);
-                    Err(Status::ok("Stream complete"))
+                    let status = stats_for_end.close_status.lock().unwrap().take();
+                    Err(status.unwrap_or_else(|| Status::ok("Stream complete")))
                 })),
         );
 
-        println!("← Exiting subscribe_to_logs");
         info!("✅ Stream setup complete for client: {}", client_id);
-        Ok(Response::new(mapped_stream))
+        Ok((mapped_stream, stats, control_token))
+    }
+
+    /// Apply a control message to the subscriber `client_id`, sent with the
+    /// token of `identity`. With `session`, only if that subscriber is still
+    /// the one of this Watch session, otherwise only with its `control_token`.
+    async fn control(
+        &self,
+        client_id: &str,
+        identity: Option<&str>,
+        session: Option<&Arc<ClientStats>>,
+        control_token: Option<&str>,
+        request: control_request::Request,
+    ) -> Result<(), Status> {
+        let mut clients = self.clients.lock().await;
+        // The session is over if the client was removed or replaced
+        let Some(subscriber) = clients.get_mut(client_id).filter(|subscriber| {
+            session.is_none_or(|stats| Arc::ptr_eq(&subscriber.stats, stats))
+        }) else {
+            return Err(Status::not_found(format!(
+                "Client {} is not subscribed",
+                client_id
+            )));
+        };
+        // With authentication, only the identity that subscribed steers the
+        // subscription
+        if subscriber.info.identity.is_some() && subscriber.info.identity.as_deref() != identity {
+            return Err(Status::permission_denied(
+                "The subscription belongs to another identity",
+            ));
+        }
+        // Client ids are easy to guess, and without authentication there is
+        // no identity to compare
+        if session.is_none()
+            && !control_token.is_some_and(|token| {
+                auth::constant_time_eq(token.as_bytes(), subscriber.control_token.as_bytes())
+            })
+        {
+            return Err(Status::permission_denied("Invalid control token"));
+        }
+
+        match request {
+            control_request::Request::UpdateFilter(filter) => {
+                let filter = SubscriptionFilter::from_proto(Some(filter))?;
+                info!("Updated filter of client {}: {:?}", client_id, filter);
+                subscriber.filter = filter;
+            }
+            control_request::Request::Pause(_) => {
+                if subscriber.paused.is_none() {
+                    // Taken under the clients lock, so it splits messages
+                    // exactly between delivered and missed
+                    subscriber.paused = Some(self.history.lock().await.sequences().clone());
+                    info!("Paused client {}", client_id);
+                }
+            }
+            control_request::Request::Resume(_) => {
+                if let Some(cursor) = subscriber.paused.take() {
+                    let history = self.history.lock().await;
+                    let (replay, gaps) =
+                        history.resume(&cursor, "", |log| subscriber.is_visible(log));
+                    info!(
+                        "Resumed client {} with {} missed messages",
+                        client_id,
+                        replay.len()
+                    );
+                    for log in gaps.into_iter().map(gap_message).chain(replay) {
+                        if subscriber.send(log).is_err() {
+                            clients.remove(client_id);
+                            return Err(Status::resource_exhausted(
+                                "Missed messages exceed the subscriber queue capacity",
+                            ));
+                        }
+                    }
+                }
+            }
+            control_request::Request::Ack(cursor) => {
+                for (server_id, sequence) in cursor.last_sequences {
+                    let acked = subscriber
+                        .info
+                        .acked_sequences
+                        .entry(server_id)
+                        .or_default();
+                    *acked = (*acked).max(sequence);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// End a Watch session with `status`, unless its client was already
+    /// removed or replaced
+    async fn end_session(&self, client_id: &str, session: &Arc<ClientStats>, status: Status) {
+        let mut clients = self.clients.lock().await;
+        if clients
+            .get(client_id)
+            .is_some_and(|subscriber| Arc::ptr_eq(&subscriber.stats, session))
+        {
+            if let Some(subscriber) = clients.remove(client_id) {
+                subscriber.close_with(status);
+            }
+        }
+    }
+
+    pub async fn check_connection(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // Try to acquire the lock on clients - if we can't, server might be down
+        if let Ok(clients) = self.clients.try_lock() {
+            // Check if we have any active clients
+            if !clients.is_empty() {
+                return Ok(());
+            }
+        }
+
+        // If we got here, either lock failed or no clients - consider connection dead
+        Err("Connection lost to logging server".into())
+    }
+}
+
+/// Part of the service the dispatcher task works with. It holds no
+/// dispatcher sender, so the task ends once every `LoggingService` clone,
+/// and with them the sender, is dropped.
+struct Dispatcher {
+    clients: Arc<Mutex<HashMap<String, Subscriber>>>,
+    history: Arc<Mutex<LogHistory>>,
+    log_all_messages: Arc<AtomicBool>,
+    pipeline_running: Arc<AtomicBool>,
+    health: Arc<Mutex<Option<HealthReporter>>>,
+}
+
+impl Dispatcher {
+    async fn run(self, mut receiver: mpsc::UnboundedReceiver<Dispatch>) {
+        // Whoever installed the tracing subscriber, messages are delivered
+        // as long as this task runs
+        self.pipeline_running.store(true, Ordering::SeqCst);
+        report_health(&self.health, true).await;
+
+        let mut batch = Vec::with_capacity(DISPATCH_BATCH);
+        while receiver.recv_many(&mut batch, DISPATCH_BATCH).await > 0 {
+            // Take the locks once for everything that is ready
+            let mut clients = self.clients.lock().await;
+            let mut history = self.history.lock().await;
+            for dispatch in batch.drain(..) {
+                match dispatch {
+                    Dispatch::Log(log) => {
+                        let log_all = self.log_all_messages.load(Ordering::Relaxed);
+                        deliver(&clients, &mut history, *log, log_all);
+                    }
+                    Dispatch::Flush(done) => {
+                        let _ = done.send(());
+                    }
+                }
+            }
+            drop(history);
+            remove_dead_clients(&mut clients);
+        }
+
+        self.pipeline_running.store(false, Ordering::SeqCst);
+        report_health(&self.health, false).await;
+    }
+}
+
+#[tonic::async_trait]
+impl LogService for LoggingService {
+    type SubscribeToLogsStream = LogStream;
+    async fn subscribe_to_logs(
+        &self,
+        request: Request<SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeToLogsStream>, Status> {
+        println!("→ Entering subscribe_to_logs");
+        let peer_address = request.remote_addr();
+        let identity = auth::identity(&request);
+
+        let (stream, _, control_token) = self
+            .subscribe(request.into_inner(), peer_address, identity)
+            .await?;
+        println!("← Exiting subscribe_to_logs");
+        Ok(with_control_token(Response::new(stream), &control_token))
+    }
+
+    type SubscribeToLogBatchesStream = LogBatchStream;
+    async fn subscribe_to_log_batches(
+        &self,
+        request: Request<SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeToLogBatchesStream>, Status> {
+        let peer_address = request.remote_addr();
+        let identity = auth::identity(&request);
+        let mut request = request.into_inner();
+        let batch = request.batch.take().unwrap_or_default();
+        let max_messages = match batch.max_messages {
+            0 => DEFAULT_BATCH_MESSAGES,
+            n => (n as usize).min(MAX_BATCH_MESSAGES),
+        };
+        let max_delay = match batch.max_delay_ms {
+            0 => DEFAULT_BATCH_DELAY_MS,
+            n => n as u64,
+        };
+
+        let (stream, _, control_token) = self.subscribe(request, peer_address, identity).await?;
+        let stream = batched(
+            stream,
+            max_messages,
+            std::time::Duration::from_millis(max_delay),
+        );
+        Ok(with_control_token(Response::new(stream), &control_token))
+    }
+
+    type WatchStream = LogStream;
+    async fn watch(
+        &self,
+        request: Request<Streaming<WatchRequest>>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let peer_address = request.remote_addr();
+        let identity = auth::identity(&request);
+        let mut requests = request.into_inner();
+
+        let subscribe = match requests.message().await? {
+            Some(WatchRequest {
+                request: Some(watch_request::Request::Subscribe(subscribe)),
+            }) => subscribe,
+            _ => {
+                return Err(Status::invalid_argument(
+                    "The first Watch message must be subscribe",
+                ))
+            }
+        };
+        let client_id = subscribe.client_id.clone();
+        // The session itself is the proof of ownership, no token needed
+        let (stream, stats, _) = self
+            .subscribe(subscribe, peer_address, identity.clone())
+            .await?;
+
+        // Apply control messages until the client stops sending them. The
+        // response stream lives on after a half-close.
+        let service = self.clone();
+        tokio::spawn(async move {
+            while let Ok(Some(request)) = requests.message().await {
+                let control = match request.request {
+                    None => continue,
+                    Some(watch_request::Request::Subscribe(_)) => {
+                        Err(Status::invalid_argument("Watch session already subscribed"))
+                    }
+                    Some(watch_request::Request::UpdateFilter(filter)) => {
+                        Ok(control_request::Request::UpdateFilter(filter))
+                    }
+                    Some(watch_request::Request::Pause(pause)) => {
+                        Ok(control_request::Request::Pause(pause))
+                    }
+                    Some(watch_request::Request::Resume(resume)) => {
+                        Ok(control_request::Request::Resume(resume))
+                    }
+                    Some(watch_request::Request::Ack(cursor)) => {
+                        Ok(control_request::Request::Ack(cursor))
+                    }
+                };
+                let result = match control {
+                    Ok(control) => {
+                        service
+                            .control(&client_id, identity.as_deref(), Some(&stats), None, control)
+                            .await
+                    }
+                    Err(status) => Err(status),
+                };
+                if let Err(status) = result {
+                    service.end_session(&client_id, &stats, status).await;
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(stream))
+    }
+
+    async fn control_subscription(
+        &self,
+        request: Request<ControlRequest>,
+    ) -> Result<Response<ControlResponse>, Status> {
+        let identity = auth::identity(&request);
+        let request = request.into_inner();
+        let control = request
+            .request
+            .ok_or_else(|| Status::invalid_argument("Missing control request"))?;
+
+        self.control(
+            &request.client_id,
+            identity.as_deref(),
+            None,
+            Some(&request.control_token),
+            control,
+        )
+        .await?;
+        Ok(Response::new(ControlResponse {}))
+    }
+
+    async fn push_logs(
+        &self,
+        request: Request<Streaming<LogMessage>>,
+    ) -> Result<Response<PushLogsResponse>, Status> {
+        let peer = request
+            .remote_addr()
+            .map_or_else(|| "unknown".to_string(), |addr| addr.to_string());
+        info!("📥 Log producer connected from {}", peer);
+
+        let mut stream = request.into_inner();
+        let mut accepted = 0;
+        while let Some(log) = stream.message().await? {
+            self.broadcast_log(log).await;
+            accepted += 1;
+        }
+
+        info!("Log producer {} disconnected after {} messages", peer, accepted);
+        Ok(Response::new(PushLogsResponse { accepted }))
+    }
+
+    async fn query_logs(
+        &self,
+        request: Request<QueryLogsRequest>,
+    ) -> Result<Response<QueryLogsResponse>, Status> {
+        const DEFAULT_PAGE_SIZE: usize = 100;
+        const MAX_PAGE_SIZE: usize = 1000;
+
+        let request = request.into_inner();
+        let filter = SubscriptionFilter::from_proto(request.filter)?;
+        let before_id = match request.page_token.as_str() {
+            "" => None,
+            token => Some(
+                token
+                    .parse::<u64>()
+                    .map_err(|_| Status::invalid_argument("Invalid page_token"))?,
+            ),
+        };
+        let query = HistoryQuery {
+            since: timestamp_arg("since", request.since)?,
+            until: timestamp_arg("until", request.until)?,
+            before_id,
+            limit: match request.page_size as usize {
+                0 => DEFAULT_PAGE_SIZE,
+                size => size.min(MAX_PAGE_SIZE),
+            },
+        };
+        let text = request.text.map(|text| text.to_lowercase());
+
+        let (logs, next) = self.history.lock().await.query(&query, |log| {
+            // Targeted messages are private to their recipient
+            log.target_client_id.is_none()
+                && filter.matches(log)
+                && text
+                    .as_ref()
+                    .is_none_or(|text| log.message.to_lowercase().contains(text))
+        });
+
+        Ok(Response::new(QueryLogsResponse {
+            logs,
+            next_page_token: next.map(|id| id.to_string()).unwrap_or_default(),
+        }))
+    }
+}
+
+/// Span wrapping each incoming request, continuing the caller's trace when
+/// it sent a `traceparent` header. The `grpc` output enables spans whatever
+/// its filter, so the trace reaches subscribers even when the filter
+/// disables debug.
+fn request_span(request: &tonic::codegen::http::Request<()>) -> tracing::Span {
+    let path = request.uri().path();
+    match request
+        .headers()
+        .get(TRACEPARENT)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(traceparent) => tracing::debug_span!("grpc_request", path, traceparent),
+        None => tracing::debug_span!("grpc_request", path),
     }
 }
 
+/// `response` with the control token of its subscription in its metadata
+fn with_control_token<T>(mut response: Response<T>, control_token: &str) -> Response<T> {
+    if let Ok(value) = control_token.parse() {
+        response.metadata_mut().insert(CONTROL_TOKEN, value);
+    }
+    response
+}
+
+/// Deliver a message to the subscribers while the caller holds the
+/// clients and history locks.
+///
+/// Holding the clients lock while retaining means subscribers joining now
+/// see each message exactly once, either replayed or live.
+fn deliver(
+    clients: &HashMap<String, Subscriber>,
+    history: &mut LogHistory,
+    mut log: LogMessage,
+    log_all: bool,
+) -> bool {
+    // Skip internal messages unless explicitly configured to log all.
+    // Messages addressed to a client are never internal.
+    if log.target_client_id.is_none() && !log_all && is_internal_message(&log) {
+        return true;
+    }
+    let connected = log
+        .target_client_id
+        .as_ref()
+        .is_none_or(|target| clients.contains_key(target));
+
+    history.stamp(&mut log);
+    history.push(log.clone());
+
+    for (client_id, subscriber) in clients.iter() {
+        // Apply the filter the client subscribed with
+        if subscriber.paused.is_some() || !subscriber.filter.matches(&log) {
+            continue;
+        }
+
+        // Only send targeted messages to their target client. A failed
+        // send marks the subscriber's queue closed, it is removed after.
+        if log
+            .target_client_id
+            .as_ref()
+            .is_none_or(|target_id| target_id == client_id)
+        {
+            let _ = subscriber.send(log.clone());
+        }
+    }
+    connected
+}
+
+/// Publish the serving status of every service through `grpc.health.v1.Health`
+async fn report_health(health: &Mutex<Option<HealthReporter>>, serving: bool) {
+    let status = if serving {
+        ServingStatus::Serving
+    } else {
+        ServingStatus::NotServing
+    };
+    if let Some(reporter) = health.lock().await.as_mut() {
+        for name in [
+            "",
+            <LogServiceServer<LoggingService> as NamedService>::NAME,
+            <AdminServiceServer<LoggingService> as NamedService>::NAME,
+            <logging::log_service_server::LogServiceServer<LegacyLogService> as NamedService>::NAME,
+        ] {
+            reporter.set_service_status(name, status).await;
+        }
+    }
+}
+
+/// The filter all outputs share, empty if they differ. `None` without outputs.
+pub(crate) fn shared_filter(filters: &[String]) -> Option<String> {
+    let first = filters.first()?;
+    Some(if filters.iter().all(|filter| filter == first) {
+        first.clone()
+    } else {
+        String::new()
+    })
+}
+
+/// Message generated by the server to report messages a subscriber won't receive
+fn gap_message(gap: SequenceGap) -> LogMessage {
+    let now = chrono::Local::now();
+    let message = if gap.restarted {
+        format!(
+            "The server restarted, messages from '{}' after sequence {} may have been lost",
+            gap.server_id,
+            gap.first_missing - 1
+        )
+    } else {
+        format!(
+            "{} messages from '{}' are no longer retained and were skipped",
+            gap.last_missing - gap.first_missing + 1,
+            gap.server_id
+        )
+    };
+    LogMessage {
+        timestamp: Some(to_timestamp(now.to_utc())),
+        utc_offset_secs: now.offset().local_minus_utc(),
+        level: Level::Warn as i32,
+        message,
+        server_id: Some(gap.server_id.clone()),
+        gap: Some(gap),
+        ..Default::default()
+    }
+}
+
+fn timestamp_arg(
+    field: &str,
+    value: Option<prost_types::Timestamp>,
+) -> Result<Option<DateTime<Utc>>, Status> {
+    value
+        .map(|timestamp| {
+            from_timestamp(&timestamp)
+                .ok_or_else(|| Status::invalid_argument(format!("Invalid {}", field)))
+        })
+        .transpose()
+}
+
+/// Coalesce a subscriber's stream into batches of up to `max_messages`, each
+/// sent at most `max_delay` after its first message
+fn batched(stream: LogStream, max_messages: usize, max_delay: std::time::Duration) -> LogBatchStream {
+    let chunks = tokio_stream::StreamExt::chunks_timeout(stream, max_messages, max_delay);
+    Box::pin(chunks.flat_map(|chunk| {
+        let mut logs = Vec::with_capacity(chunk.len());
+        let mut end = None;
+        for item in chunk {
+            match item {
+                Ok(log) => logs.push(log),
+                // The status ending the stream always comes last
+                Err(status) => end = Some(status),
+            }
+        }
+
+        let batch = (!logs.is_empty()).then(|| Ok(LogBatch { logs }));
+        futures::stream::iter(batch.into_iter().chain(end.map(Err)))
+    }))
+}
+
+/// Remove the subscribers whose queue was closed or overflowed
+fn remove_dead_clients(clients: &mut HashMap<String, Subscriber>) {
+    clients.retain(|client_id, subscriber| {
+        let alive = !subscriber.queue.is_closed();
+        if !alive {
+            warn!("Removed disconnected client: {}", client_id);
+        }
+        alive
+    });
+}
+
 fn is_internal_message(log: &LogMessage) -> bool {
     const INTERNAL_PREFIXES: &[&str] = &[
         "h2::",
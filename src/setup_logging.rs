@@ -1,179 +1,186 @@
-use crate::grpc::GrpcLayer;
+use crate::grpc::{GrpcLayer, GrpcSink, LogForwarder};
 use crate::server_build::LoggingService;
 use tracing::Level;
-use tracing_appender::non_blocking::NonBlocking;
-use tracing_appender::rolling::{RollingFileAppender, Rotation};
-use tracing_subscriber::EnvFilter;
+use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
+use tracing_subscriber::{reload, EnvFilter};
 use std::io;
+use std::sync::Mutex;
 
-use crate::config::CustomTimer;
 use tracing_subscriber::Layer;
 use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
 use crate::config::CustomFormatter;
 use crate::config::LogConfig;
 use tracing_subscriber::Registry;
-use crate::config::LogOutput;
+use crate::config::{LogOutput, OutputConfig};
+use crate::fields::{FieldsFormatter, SpanFieldsLayer};
+use crate::rolling::RollingWriter;
 use tracing_subscriber::fmt::layer;
-use crate::server_build::logging::log_service_client::LogServiceClient;
-use tonic::Request;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::registry::LookupSpan;
 use uuid::Uuid;
-use crate::server_build::logging::ClientType;
-use crate::server_build::logging::SubscribeRequest;
 
-pub async fn setup_logging(config: &LogConfig) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let level = match config.level.to_lowercase().as_str() {
+/// Handle to the filter of an output installed by `setup_logging_internal`,
+/// used to change it at runtime
+pub(crate) type FilterHandle = reload::Handle<EnvFilter, Registry>;
+
+type BoxedLayer<S> = Box<dyn Layer<S> + Send + Sync + 'static>;
+type OutputFilter<S> = reload::Layer<EnvFilter, S>;
+
+// File writers of `setup_logging`, flushed as long as the process runs
+static FILE_GUARDS: Mutex<Vec<WorkerGuard>> = Mutex::new(Vec::new());
+
+fn parse_level(level: &str) -> Level {
+    match level.to_lowercase().as_str() {
         "trace" => Level::TRACE,
         "debug" => Level::DEBUG,
         "info" => Level::INFO,
         "warn" => Level::WARN,
         "error" => Level::ERROR,
         _ => Level::INFO,
-    };
+    }
+}
 
-    // Create a new LoggingService for forwarding logs
-    let logging_service = LoggingService::new();
-
-    let subscriber = Registry::default();
-    let fmt_layer = layer()
-        .with_writer(io::stdout)
-        .with_timer(CustomTimer)
-        .with_target(false)
-        .with_thread_ids(false)
-        .with_file(false)
-        .with_line_number(false)
-        .with_ansi(false)
-        .with_level(true)
-        .with_thread_names(false)
-        .with_filter(EnvFilter::new("")
-            .add_directive("logger_to_client=info".parse()?)
-            .add_directive("warn".parse()?)
-            .add_directive(level.into()));
-
-    // Connect to gRPC logger if configured
-    if let Some(grpc_config) = &config.grpc {
-        let addr = format!("http://{}:{}", grpc_config.address, grpc_config.port);
-        println!("Attempting to connect to grpc-logger at {}", addr);
-        
-        let server_id = config.server_id.clone()
-            .unwrap_or_else(|| format!("server-{}", Uuid::new_v4()));
-        println!("Creating subscription request for server: {}", server_id);
-        
-        let request = Request::new(SubscribeRequest {
-            client_id: server_id.clone(),
-            client_type: ClientType::Server as i32,
-            server_name: server_id.clone(),
-        });
-
-        // Connect to gRPC server
-        let mut client = LogServiceClient::connect(addr).await?;
-        println!("Connected to gRPC server");
-        
-        // Start the subscription in a separate task
-        let response = client.subscribe_to_logs(request).await?;
-        let mut stream = response.into_inner();
-        
-        // Spawn a task to handle the stream
-        tokio::spawn(async move {
-            while let Ok(Some(log)) = stream.message().await {
-                println!("Received log from server: {:?}", log);
-            }
-            println!("Stream ended");
-        });
+fn output_level(config: &LogConfig, output: &OutputConfig) -> Level {
+    parse_level(output.level.as_deref().unwrap_or(&config.level))
+}
 
-        println!("Successfully subscribed to grpc-logger");
+/// Console or file layer writing events with `formatter`
+fn fmt_layer<S, W>(formatter: CustomFormatter, writer: W, filter: OutputFilter<S>) -> BoxedLayer<S>
+where
+    S: tracing::Subscriber + for<'lookup> LookupSpan<'lookup>,
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    layer()
+        .event_format(formatter)
+        .fmt_fields(FieldsFormatter)
+        .with_writer(writer)
+        .with_filter(filter)
+        .boxed()
+}
 
-        // Create the GRPC layer
-        let grpc_layer = GrpcLayer {
-            service: logging_service,
-            config: config.log_fields.clone(),
-            server_id: Some(server_id),
+/// One layer per configured output, each filtered at its own level on top of
+/// `RUST_LOG`. `grpc` outputs deliver to `sink`, as coming from `server_id`,
+/// and are skipped when there is no sink. The handles to the filters of the
+/// outputs are added to `handles`, in the order of the outputs.
+fn output_layers<S>(
+    config: &LogConfig,
+    server_id: Option<String>,
+    sink: Option<GrpcSink>,
+    guards: &mut Vec<WorkerGuard>,
+    handles: &mut Vec<reload::Handle<EnvFilter, S>>,
+) -> io::Result<Vec<BoxedLayer<S>>>
+where
+    S: tracing::Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    let mut layers: Vec<BoxedLayer<S>> = Vec::with_capacity(config.output.len() + 1);
+    // Span fields of the console and file outputs, recorded once for all of them
+    if config.output.iter().any(|output| output.output != LogOutput::Grpc) {
+        layers.push(SpanFieldsLayer.boxed());
+    }
+    for output in &config.output {
+        let level = LevelFilter::from_level(output_level(config, output));
+        let (filter, handle) =
+            reload::Layer::new(EnvFilter::from_default_env().add_directive(level.into()));
+        let fields = output
+            .log_fields
+            .clone()
+            .unwrap_or_else(|| config.log_fields.clone());
+
+        let formatter = CustomFormatter {
+            server_id: config.server_id.clone(),
+            config: fields.clone(),
+            format: output.format.unwrap_or(config.format),
         };
 
-        // Set up the subscriber with both layers
-        tracing::subscriber::set_global_default(subscriber.with(fmt_layer).with(grpc_layer))
-            .expect("Failed to set subscriber");
-    } else {
-        // Just use the standard subscriber
-        tracing::subscriber::set_global_default(subscriber.with(fmt_layer))
-            .expect("Failed to set subscriber");
+        let output_layer: BoxedLayer<S> = match output.output {
+            LogOutput::Console => fmt_layer(formatter, io::stdout, filter),
+            LogOutput::File => {
+                let file_path = output
+                    .file_path
+                    .as_deref()
+                    .or(config.file_path.as_deref())
+                    .unwrap_or("logs");
+                let file_name = config.file_name(output);
+                let writer = RollingWriter::new(file_path, &file_name, config)?;
+                let (non_blocking, guard) = NonBlocking::new(writer);
+                guards.push(guard);
+
+                fmt_layer(formatter, non_blocking, filter)
+            }
+            LogOutput::Grpc => match &sink {
+                Some(sink) => GrpcLayer {
+                    sink: sink.clone(),
+                    config: fields,
+                    server_id: server_id.clone(),
+                }
+                .with_event_filter(filter)
+                .boxed(),
+                None => continue,
+            },
+        };
+        layers.push(output_layer);
+        handles.push(handle);
     }
+    Ok(layers)
+}
+
+pub async fn setup_logging(config: &LogConfig) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Forward our own events to the gRPC logger if configured
+    let wants_grpc = config
+        .output
+        .iter()
+        .any(|output| output.output == LogOutput::Grpc);
+    let sink = match &config.grpc {
+        Some(grpc_config) if wants_grpc => {
+            let forwarder = LogForwarder::connect(grpc_config, &config.client_retry)?;
+            println!(
+                "Forwarding logs to grpc-logger at {}:{}",
+                grpc_config.address, grpc_config.port
+            );
+            Some(GrpcSink::Forward(forwarder))
+        }
+        _ => None,
+    };
+
+    // Forwarded messages need an origin
+    let server_id = config
+        .server_id
+        .clone()
+        .unwrap_or_else(|| format!("server-{}", Uuid::new_v4()));
+
+    let mut guards = Vec::new();
+    let layers = output_layers(config, Some(server_id), sink, &mut guards, &mut Vec::new())?;
+    tracing::subscriber::set_global_default(Registry::default().with(layers))
+        .expect("Failed to set subscriber");
+    FILE_GUARDS.lock().unwrap().extend(guards);
 
     println!("Logging setup complete");
     Ok(())
 }
 
+/// Install one layer per configured output, `grpc` outputs broadcasting to
+/// `grpc_service`. Returns the guards of the file writers, which stop
+/// flushing once dropped.
 pub async fn setup_logging_internal(
     config: &LogConfig,
     grpc_service: Option<LoggingService>,
-) -> Result<
-    Option<tracing_appender::non_blocking::WorkerGuard>,
-    Box<dyn std::error::Error + Sync + Send>,
-> {
-    let level = match config.level.to_lowercase().as_str() {
-        "trace" => Level::TRACE,
-        "debug" => Level::DEBUG,
-        "info" => Level::INFO,
-        "warn" => Level::WARN,
-        "error" => Level::ERROR,
-        _ => Level::INFO,
-    };
-
-    let env_filter = EnvFilter::new("")
-        .add_directive("logger_to_client=info".parse()?)
-        .add_directive("warn".parse()?);
-
-    let subscriber = Registry::default();
-    let format = CustomFormatter {
-        server_id: config.server_id.clone(),
-        config: config.log_fields.clone(),
-    };
-
-    match config.output {
-        LogOutput::File => {
-            let file_path = config.file_path.as_deref().unwrap_or("logs");
-            let file_name = config.file_name.as_deref().unwrap_or("app.log");
-
-            let file_appender = RollingFileAppender::new(Rotation::NEVER, file_path, file_name);
-            let (non_blocking, guard) = NonBlocking::new(file_appender);
-
-            let layer = layer()
-                .event_format(format)
-                .with_writer(non_blocking)
-                .with_filter(env_filter.add_directive(level.into()));
-
-            tracing::subscriber::set_global_default(subscriber.with(layer).with(grpc_service.map(
-                |service| GrpcLayer {
-                    service,
-                    config: config.log_fields.clone(),
-                    server_id: config.server_id.clone(),
-                },
-            )))
-            .expect("Failed to set subscriber");
-            Ok(Some(guard))
-        }
-        LogOutput::Console | LogOutput::Grpc => {
-            let layer = layer()
-                .with_writer(io::stdout)
-                .with_timer(CustomTimer)
-                .with_target(false)
-                .with_thread_ids(false)
-                .with_file(false)
-                .with_line_number(false)
-                .with_ansi(false)
-                .with_level(true)
-                .with_thread_names(false)
-                .with_filter(EnvFilter::from_default_env().add_directive(level.into()));
-
-            tracing::subscriber::set_global_default(subscriber.with(layer).with(grpc_service.map(
-                |service| GrpcLayer {
-                    service,
-                    config: config.log_fields.clone(),
-                    server_id: config.server_id.clone(),
-                },
-            )))
-            .expect("Failed to set subscriber");
-            Ok(None)
-        }
+) -> Result<Vec<WorkerGuard>, Box<dyn std::error::Error + Sync + Send>> {
+    let mut guards = Vec::new();
+    let mut handles = Vec::new();
+    let layers = output_layers(
+        config,
+        config.server_id.clone(),
+        grpc_service.clone().map(GrpcSink::Local),
+        &mut guards,
+        &mut handles,
+    )?;
+    // The filter of every output, gRPC included, can be replaced at runtime
+    // by the SetFilter RPC
+    if let Some(service) = &grpc_service {
+        service.set_filter_handles(handles).await;
     }
+    tracing::subscriber::set_global_default(Registry::default().with(layers))
+        .expect("Failed to set subscriber");
+    Ok(guards)
 }
-
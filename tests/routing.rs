@@ -0,0 +1,123 @@
+//! Routing and trace context of `GrpcLayer` events emitted in spans below
+//! the level of the layer's filter.
+
+use grpc_logger::config::LogFieldsConfig;
+use grpc_logger::grpc::{CompressionConfig, GrpcConfig, GrpcLayer, GrpcSink};
+use grpc_logger::server_build::logging::v2::log_service_client::LogServiceClient;
+use grpc_logger::server_build::logging::v2::{LogMessage, SubscribeRequest};
+use grpc_logger::{LogConfig, LoggingService};
+use std::time::Duration;
+use tonic::Streaming;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+async fn start_server(port: u16) -> LoggingService {
+    let config = LogConfig {
+        grpc: Some(GrpcConfig {
+            address: "127.0.0.1".to_string(),
+            port,
+            compression: CompressionConfig::default(),
+            auth: None,
+            tls: None,
+            forward_buffer: 10000,
+        }),
+        ..LogConfig::default()
+    };
+    let service = LoggingService::new();
+    service.init(&config).await.unwrap();
+    service
+}
+
+async fn subscribe(service: &LoggingService, port: u16, client_id: &str) -> Streaming<LogMessage> {
+    let mut client = LogServiceClient::connect(format!("http://127.0.0.1:{}", port))
+        .await
+        .unwrap();
+    let stream = client
+        .subscribe_to_logs(SubscribeRequest {
+            client_id: client_id.to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    while !service.is_client_connected(client_id).await {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    stream
+}
+
+/// Messages of this test received until the stream stays idle
+async fn received(stream: &mut Streaming<LogMessage>) -> Vec<LogMessage> {
+    let mut logs = Vec::new();
+    while let Ok(Ok(Some(log))) =
+        tokio::time::timeout(Duration::from_millis(500), stream.message()).await
+    {
+        if log.target.as_deref() == Some(module_path!()) {
+            logs.push(log);
+        }
+    }
+    logs
+}
+
+fn messages(logs: &[LogMessage]) -> Vec<&str> {
+    logs.iter().map(|log| log.message.as_str()).collect()
+}
+
+fn warn_layer(service: &LoggingService) -> impl tracing::Subscriber {
+    Registry::default().with(
+        GrpcLayer {
+            sink: GrpcSink::Local(service.clone()),
+            config: LogFieldsConfig::default(),
+            server_id: Some("routing".to_string()),
+        }
+        .with_event_filter(LevelFilter::WARN),
+    )
+}
+
+#[tokio::test]
+async fn client_id_of_a_span_below_the_filter_level_routes() {
+    let service = start_server(50075).await;
+    let mut a = subscribe(&service, 50075, "a").await;
+    let mut b = subscribe(&service, 50075, "b").await;
+
+    tracing::subscriber::with_default(warn_layer(&service), || {
+        let span = tracing::info_span!("req", client_id = "a");
+        let _guard = span.enter();
+        tracing::info!("filtered out");
+        tracing::warn!("private to a");
+    });
+    tracing::subscriber::with_default(warn_layer(&service), || {
+        tracing::warn!("for everyone");
+    });
+    service.flush().await;
+
+    let to_a = received(&mut a).await;
+    assert_eq!(messages(&to_a), ["private to a", "for everyone"]);
+    assert_eq!(to_a[0].target_client_id.as_deref(), Some("a"));
+    assert_eq!(messages(&received(&mut b).await), ["for everyone"]);
+}
+
+#[tokio::test]
+async fn trace_of_a_span_below_the_filter_level_is_kept() {
+    let service = start_server(50076).await;
+    let mut stream = subscribe(&service, 50076, "tracer").await;
+
+    tracing::subscriber::with_default(warn_layer(&service), || {
+        let span = tracing::debug_span!(
+            "grpc_request",
+            traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        );
+        let _guard = span.enter();
+        tracing::warn!("traced");
+    });
+    service.flush().await;
+
+    let logs = received(&mut stream).await;
+    assert_eq!(messages(&logs), ["traced"]);
+    assert_eq!(
+        logs[0].trace_id.as_deref(),
+        Some("4bf92f3577b34da6a3ce929d0e0e4736")
+    );
+    assert_eq!(logs[0].parent_span_id.as_deref(), Some("00f067aa0ba902b7"));
+}
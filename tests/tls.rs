@@ -0,0 +1,153 @@
+//! TLS and mutual TLS between the log server and its clients, with
+//! certificates generated for each test.
+
+use grpc_logger::grpc::{CompressionConfig, GrpcConfig, TlsConfig};
+use grpc_logger::server_build::logging::v2::log_service_client::LogServiceClient;
+use grpc_logger::server_build::logging::v2::QueryLogsRequest;
+use grpc_logger::{LogConfig, LoggingService};
+use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+use std::fs;
+use std::path::PathBuf;
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// PEM files of a CA and of a server and a client certificate it signed
+struct Certs {
+    dir: PathBuf,
+}
+
+impl Certs {
+    fn generate(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("grpc-logger-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca_key = KeyPair::generate().unwrap();
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+
+        for entity in ["server", "client"] {
+            let key = KeyPair::generate().unwrap();
+            let cert = CertificateParams::new(vec!["localhost".to_string()])
+                .unwrap()
+                .signed_by(&key, &ca, &ca_key)
+                .unwrap();
+            fs::write(dir.join(format!("{}.pem", entity)), cert.pem()).unwrap();
+            fs::write(dir.join(format!("{}.key", entity)), key.serialize_pem()).unwrap();
+        }
+
+        Self { dir }
+    }
+
+    fn path(&self, file: &str) -> Option<String> {
+        Some(self.dir.join(file).to_string_lossy().into_owned())
+    }
+}
+
+impl Drop for Certs {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn grpc_config(port: u16, tls: Option<TlsConfig>) -> GrpcConfig {
+    GrpcConfig {
+        address: "127.0.0.1".to_string(),
+        port,
+        compression: CompressionConfig::default(),
+        auth: None,
+        tls,
+        forward_buffer: 10000,
+    }
+}
+
+async fn start_server(grpc: GrpcConfig) {
+    let config = LogConfig {
+        grpc: Some(grpc),
+        ..LogConfig::default()
+    };
+    LoggingService::new().init(&config).await.unwrap();
+}
+
+async fn query(grpc: &GrpcConfig) -> Result<(), Error> {
+    let channel = grpc.endpoint()?.connect().await?;
+    LogServiceClient::new(channel)
+        .query_logs(QueryLogsRequest::default())
+        .await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn serves_over_tls() {
+    let certs = Certs::generate("tls");
+    start_server(grpc_config(
+        50071,
+        Some(TlsConfig {
+            cert: certs.path("server.pem"),
+            key: certs.path("server.key"),
+            ..TlsConfig::default()
+        }),
+    ))
+    .await;
+
+    let trusted = grpc_config(
+        50071,
+        Some(TlsConfig {
+            server_ca: certs.path("ca.pem"),
+            domain: Some("localhost".to_string()),
+            ..TlsConfig::default()
+        }),
+    );
+    query(&trusted).await.unwrap();
+
+    // The generated CA is not among the system roots
+    let untrusted = grpc_config(
+        50071,
+        Some(TlsConfig {
+            domain: Some("localhost".to_string()),
+            ..TlsConfig::default()
+        }),
+    );
+    assert!(query(&untrusted).await.is_err());
+
+    let plaintext = grpc_config(50071, None);
+    assert!(query(&plaintext).await.is_err());
+}
+
+#[tokio::test]
+async fn mutual_tls_requires_client_certificate() {
+    let certs = Certs::generate("mtls");
+    start_server(grpc_config(
+        50072,
+        Some(TlsConfig {
+            cert: certs.path("server.pem"),
+            key: certs.path("server.key"),
+            client_ca: certs.path("ca.pem"),
+            ..TlsConfig::default()
+        }),
+    ))
+    .await;
+
+    let anonymous = grpc_config(
+        50072,
+        Some(TlsConfig {
+            server_ca: certs.path("ca.pem"),
+            domain: Some("localhost".to_string()),
+            ..TlsConfig::default()
+        }),
+    );
+    assert!(query(&anonymous).await.is_err());
+
+    let authenticated = grpc_config(
+        50072,
+        Some(TlsConfig {
+            cert: certs.path("client.pem"),
+            key: certs.path("client.key"),
+            server_ca: certs.path("ca.pem"),
+            domain: Some("localhost".to_string()),
+            ..TlsConfig::default()
+        }),
+    );
+    query(&authenticated).await.unwrap();
+}